@@ -31,13 +31,24 @@ pub(crate) struct StaticData {
 }
 
 impl Psr4Data {
-    /// append from composer.json
-    pub fn append_json(&mut self, json: &Composer) {
+    /// keep only the entries of the vendor packages, or only those of composer.json
+    pub fn keep(&mut self, is_vendor: IsVendor) {
+        for list in self.data.values_mut() {
+            list.retain(|(v, _)| *v == is_vendor);
+        }
+        self.data.retain(|_, list| !list.is_empty());
+    }
+
+    /// append from composer.json, and its autoload-dev with `dev`
+    pub fn append_json(&mut self, json: &Composer, dev: bool) {
         let mut res = Vec::new();
-        if let Some(AutoloadEnum::Psr(Autoload {
-            psr4: Some(psr), ..
-        })) = &json.autoload
-        {
+        for autoload in json.autoloads(dev) {
+            let psr = match autoload {
+                AutoloadEnum::Psr(Autoload {
+                    psr4: Some(psr), ..
+                }) => psr,
+                _ => continue,
+            };
             for (key, value) in psr.iter() {
                 if let PsrValue::String(value) = value {
                     let mut v = "/".to_string();
@@ -64,10 +75,10 @@ impl Psr4Data {
         }
     }
 
-    /// append from composer.lock
-    pub fn append_lock(&mut self, lock: &ComposerLock) {
+    /// append from composer.lock, and its packages-dev with `dev`
+    pub fn append_lock(&mut self, lock: &ComposerLock, dev: bool) {
         let mut res = Vec::new();
-        for item in lock.packages.iter() {
+        for item in lock.installed(dev) {
             if let Some(AutoloadEnum::Psr(Autoload {
                 psr4: Some(psr), ..
             })) = &item.autoload
@@ -119,22 +130,29 @@ impl FilesData {
         self.data.insert(key, (is_vendor, value))
     }
 
-    /// append from composer.json
-    pub fn append_json(&mut self, json: &Composer) {
-        if let Some(AutoloadEnum::Psr(Autoload {
-            files: Some(files), ..
-        })) = &json.autoload
-        {
-            for it in files {
-                let con = format!("/{}", it);
-                self.insert(false, con);
+    /// keep only the entries of the vendor packages, or only those of composer.json
+    pub fn keep(&mut self, is_vendor: IsVendor) {
+        self.data.retain(|_, (v, _)| *v == is_vendor);
+    }
+
+    /// append from composer.json, and its autoload-dev with `dev`
+    pub fn append_json(&mut self, json: &Composer, dev: bool) {
+        for autoload in json.autoloads(dev) {
+            if let AutoloadEnum::Psr(Autoload {
+                files: Some(files), ..
+            }) = autoload
+            {
+                for it in files {
+                    let con = format!("/{}", it);
+                    self.insert(false, con);
+                }
             }
         }
     }
 
-    /// append from composer.lock
-    pub fn append_lock(&mut self, lock: &ComposerLock) {
-        for item in lock.packages.iter() {
+    /// append from composer.lock, and its packages-dev with `dev`
+    pub fn append_lock(&mut self, lock: &ComposerLock, dev: bool) {
+        for item in lock.installed(dev) {
             if let Some(AutoloadEnum::Psr(Autoload {
                 files: Some(files), ..
             })) = &item.autoload
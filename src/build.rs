@@ -0,0 +1,408 @@
+//! build a static composer repository, like satis
+//!
+//! The packages are the versions of `require` found in the `repositories` of the
+//! config, and the packages of its `lock` file. Their archives are copied into
+//! `dist/` of the output directory, and their metadata is written like a composer
+//! 2 repository: `packages.json` with a `metadata-url`, and `p2/<name>.json`
+//! with `p2/<name>~dev.json` for the dev versions.
+
+use std::{
+    collections::BTreeMap,
+    fs::{copy, create_dir_all, read, read_to_string, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use indexmap::IndexMap;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    auth::Auth,
+    config::{Repositories, RepositoryEntry},
+    constraint::{Constraint, Stability},
+    download::{dist_path, git_source, Downloader, MAX_PARALLEL_HTTP},
+    error::ComposerError,
+    http::{Http, HttpOptions},
+    io::ErrWriter,
+    package::{ComposerLock, Dist, Version},
+    repository::{artifact::mark_local, origin, RepositorySet},
+};
+
+/// the config of `phpp build-repo`
+#[derive(Debug, Deserialize)]
+pub struct BuildConfig {
+    /// the url the output directory is served at
+    homepage: String,
+
+    #[serde(default)]
+    repositories: Repositories,
+
+    /// the packages and the constraints of their versions to mirror
+    #[serde(default)]
+    require: IndexMap<String, String>,
+
+    /// a composer.lock whose packages are mirrored
+    #[serde(default)]
+    lock: Option<String>,
+
+    #[serde(rename = "output-dir")]
+    #[serde(default)]
+    output_dir: Option<String>,
+
+    /// `secure-http`, `cafile`, `capath` and `http-timeout`, like the `config`
+    /// of composer.json
+    #[serde(default)]
+    config: Map<String, Value>,
+
+    /// the directory of the config, relative paths are relative to it
+    #[serde(skip)]
+    base: PathBuf,
+}
+
+impl BuildConfig {
+    pub fn from_file(path: &Path) -> Result<Self, ComposerError> {
+        let mut config: Self = serde_json::from_str(&read_to_string(path)?)?;
+        config.base = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        Ok(config)
+    }
+
+    /// the output directory, `output_dir` or the `output-dir` of the config
+    pub fn output_dir(&self, output_dir: Option<&Path>) -> PathBuf {
+        match (output_dir, &self.output_dir) {
+            (Some(dir), _) => dir.to_path_buf(),
+            (None, Some(dir)) => self.base.join(dir),
+            (None, None) => self.base.join("output"),
+        }
+    }
+
+    /// the client of the downloads, with the `config` of the build and the
+    /// credentials of auth.json
+    pub fn http(&self) -> Result<Http, ComposerError> {
+        let options = HttpOptions::from_config(&self.config, &self.base);
+        Ok(Http::new(&options)?.auth(Arc::new(Auth::load()?)))
+    }
+
+    /// mirror the packages into `output`, downloading their archives with `http`
+    /// and the cache `cache_dir`
+    pub async fn build(
+        &self,
+        output: &Path,
+        cache_dir: &Path,
+        http: Http,
+        stderr: &mut dyn ErrWriter,
+    ) -> Result<(), ComposerError> {
+        let homepage = self.homepage.trim_end_matches('/');
+
+        let mut items = Vec::new();
+        if let Some(lock) = &self.lock {
+            let mut lock = ComposerLock::from_str(&read_to_string(self.base.join(lock))?)?;
+            let dirs: Vec<PathBuf> = self
+                .repositories
+                .configs()
+                .filter(|config| config._type == "artifact")
+                .map(|config| self.base.join(&config.url))
+                .collect();
+            mark_local(
+                lock.packages.iter_mut().chain(lock.packages_dev.iter_mut()),
+                &dirs,
+            );
+            items.extend(lock.installed(true).cloned());
+        }
+        if !self.require.is_empty() {
+            let configs: Vec<_> = self
+                .repositories
+                .entries
+                .iter()
+                .filter_map(|entry| match entry {
+                    RepositoryEntry::Repository { config, .. } => Some(config.clone()),
+                    RepositoryEntry::Disabled(_) => None,
+                })
+                .map(|mut config| {
+                    // relative to the config, not to the current directory
+                    if matches!(config._type.as_str(), "path" | "artifact") {
+                        config.url = self.base.join(&config.url).to_string_lossy().into_owned();
+                    }
+                    config
+                })
+                .collect();
+            let repos = RepositorySet::new(
+                &configs,
+                http.clone(),
+                cache_dir.to_path_buf(),
+                Some(Duration::ZERO),
+            )
+            .await?;
+            for (name, constraint) in self.require.iter() {
+                let constraint = Constraint::parse(constraint)?;
+                let versions = repos.versions(&name.to_lowercase(), true).await?;
+                items.extend(
+                    versions
+                        .into_iter()
+                        .filter(|item| constraint.matches(&item.version_normalized)),
+                );
+            }
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        items.retain(|item| {
+            let path = item.dist.as_ref().is_some_and(|d| d._type == "path");
+            if path {
+                stderr.write(&format!(
+                    "  - Skipping {}({}), the packages of path repositories are not mirrored",
+                    item.name.as_deref().unwrap_or_default(),
+                    item.version
+                ));
+            }
+            !path && seen.insert((item.name.clone(), item.version_normalized.clone()))
+        });
+
+        let files_dir = cache_dir.join("files");
+        Downloader::new(http, MAX_PARALLEL_HTTP)
+            .download(items.iter(), &files_dir, stderr)
+            .await?;
+
+        let mut packages: BTreeMap<String, Vec<Version>> = BTreeMap::new();
+        for mut item in items {
+            let name = item.name.clone().expect("not found name");
+            if item.dist.is_some() || git_source(&item).is_some() {
+                let archive = dist_path(&files_dir, &item);
+                let content = read(&archive)?;
+                let shasum = {
+                    use sha1::{Digest, Sha1};
+                    hex::encode(Sha1::digest(&content))
+                };
+                let file = format!(
+                    "dist/{}/{}-{}.zip",
+                    name,
+                    sanitize(&item.version),
+                    &shasum[..8]
+                );
+                let target = output.join(&file);
+                if let Some(dir) = target.parent() {
+                    create_dir_all(dir)?;
+                }
+                copy(archive, target)?;
+
+                let reference = match (&item.dist, git_source(&item)) {
+                    (Some(dist), _) if !dist.reference.is_empty() => dist.reference.clone(),
+                    (_, Some((_, reference))) => reference.to_owned(),
+                    _ => shasum.clone(),
+                };
+                item.dist = Some(Dist {
+                    url: format!("{}/{}", homepage, file),
+                    _type: "zip".to_owned(),
+                    reference,
+                    shasum: Some(shasum),
+                    integrity: None,
+                    local: false,
+                });
+            }
+            item.transport_options = None;
+            packages.entry(name).or_default().push(item);
+        }
+
+        for (name, versions) in packages.iter() {
+            let (dev, stable): (Vec<&Version>, Vec<&Version>) = versions
+                .iter()
+                .partition(|item| Stability::of(&item.version) == Stability::Dev);
+            for (file, list) in [(name.clone(), stable), (format!("{}~dev", name), dev)] {
+                write(
+                    &output.join("p2").join(format!("{}.json", file)),
+                    &json!({ "packages": { name: list } }),
+                )?;
+            }
+            stderr.write(&format!("  - Mirrored {}", name));
+        }
+
+        // an absolute path, as composer resolves it against the origin of the repository
+        let path = homepage.strip_prefix(origin(homepage)).unwrap_or_default();
+        write(
+            &output.join("packages.json"),
+            &json!({
+                "packages": {},
+                "metadata-url": format!("{}/p2/%package%.json", path),
+                "available-packages": packages.keys().collect::<Vec<_>>(),
+            }),
+        )?;
+
+        Ok(())
+    }
+}
+
+/// `version` as a part of a file name
+fn sanitize(version: &str) -> String {
+    version
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '_' | '-' => c,
+            _ => '-',
+        })
+        .collect()
+}
+
+fn write(path: &Path, json: &serde_json::Value) -> Result<(), ComposerError> {
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir)?;
+    }
+    let mut f = File::create(path)?;
+    f.write_all(serde_json::to_string(json)?.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::{Method::GET, MockServer};
+
+    use crate::{
+        config::RepositoryConfig, io::tests::TestWriter, repository::artifact::tests::archive,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_build() {
+        let upstream = MockServer::start();
+        let content = archive("foo/", json!({"name": "acme/foo"}));
+        upstream.mock(|when, then| {
+            when.method(GET).path("/foo.zip");
+            then.status(200).body(&content);
+        });
+        let package = |version: &str| {
+            json!({
+                "name": "acme/foo",
+                "version": version,
+                "dist": {"url": upstream.url("/foo.zip"), "type": "zip", "reference": version},
+            })
+        };
+
+        let mirror = MockServer::start();
+        let config: BuildConfig = serde_json::from_value(json!({
+            "homepage": mirror.url("/mirror"),
+            "repositories": [
+                {"type": "package", "package": [package("1.0.0"), package("2.0.0"), package("dev-main")]}
+            ],
+            "require": {"acme/foo": "^1.0 || dev-main"},
+        }))
+        .unwrap();
+
+        let output = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut stderr = TestWriter::new();
+        config
+            .build(
+                output.path(),
+                cache_dir.path(),
+                Http::insecure(),
+                &mut stderr,
+            )
+            .await
+            .unwrap();
+        assert!(stderr.output().contains("  - Mirrored acme/foo"));
+
+        // served at the homepage
+        for file in ["packages.json", "p2/acme/foo.json", "p2/acme/foo~dev.json"] {
+            let body = read_to_string(output.path().join(file)).unwrap();
+            mirror.mock(|when, then| {
+                when.method(GET).path(format!("/mirror/{}", file));
+                then.status(200).body(body);
+            });
+        }
+        let mirror_cache = tempfile::tempdir().unwrap();
+        let repos = RepositorySet::new(
+            &[RepositoryConfig::new("composer", &mirror.url("/mirror"))],
+            Http::insecure(),
+            mirror_cache.path().to_path_buf(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let versions = repos.versions("acme/foo", true).await.unwrap();
+        let mut pretty: Vec<&str> = versions.iter().map(|v| v.version.as_str()).collect();
+        pretty.sort();
+        assert_eq!(pretty, ["1.0.0", "dev-main"]);
+
+        let dist = versions[0].dist.as_ref().unwrap();
+        let file = dist.url.strip_prefix(&mirror.url("/mirror/")).unwrap();
+        assert!(file.starts_with("dist/acme/foo/"));
+        assert_eq!(read(output.path().join(file)).unwrap(), content);
+        {
+            use sha1::{Digest, Sha1};
+            assert_eq!(dist.shasum, Some(hex::encode(Sha1::digest(&content))));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/packages.json");
+            then.status(200);
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("build.json");
+        let http = |config: Value| {
+            std::fs::write(
+                &path,
+                json!({"homepage": "https://example.org", "config": config}).to_string(),
+            )
+            .unwrap();
+            BuildConfig::from_file(&path).unwrap().http()
+        };
+
+        // secure-http by default, not the config of a composer.json
+        let url = server.url("/packages.json");
+        let response = http(json!({})).unwrap().get(&url, Default::default()).await;
+        assert!(matches!(response, Err(ComposerError::InsecureUrl(_))));
+        let response = http(json!({"secure-http": false}))
+            .unwrap()
+            .get(&url, Default::default())
+            .await;
+        assert_eq!(response.unwrap().status(), 200);
+
+        // relative to the config
+        std::fs::create_dir(dir.path().join("certs")).unwrap();
+        assert!(http(json!({"capath": "certs"})).is_ok());
+        assert!(matches!(
+            http(json!({"cafile": "certs/missing.pem"})),
+            Err(ComposerError::Io(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_relative_repositories() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("zips")).unwrap();
+        std::fs::write(
+            dir.path().join("zips/foo-1.0.0.zip"),
+            archive("foo/", json!({"name": "acme/foo", "version": "1.0.0"})),
+        )
+        .unwrap();
+        let path = dir.path().join("build.json");
+        std::fs::write(
+            &path,
+            json!({
+                "homepage": "https://example.org",
+                "repositories": [{"type": "artifact", "url": "zips"}],
+                "require": {"acme/foo": "^1.0"},
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        // read from the directory of the config, not the current one
+        let config = BuildConfig::from_file(&path).unwrap();
+        let output = config.output_dir(None);
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut stderr = TestWriter::new();
+        config
+            .build(&output, cache_dir.path(), Http::insecure(), &mut stderr)
+            .await
+            .unwrap();
+        assert!(stderr.output().contains("  - Mirrored acme/foo"));
+        assert!(output.join("p2/acme/foo.json").exists());
+    }
+}
@@ -1,15 +1,24 @@
 use std::{
     fs::{create_dir_all, read_to_string, File},
     io::Write,
+    path::PathBuf,
 };
 
 use dirs::home_dir;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use crate::error::ComposerError;
 
 const CONFIG_DIR: &str = ".config/phpp";
 
+/// the directory of the global config and auth.json
+pub(crate) fn config_dir() -> Result<PathBuf, ComposerError> {
+    Ok(home_dir()
+        .ok_or(ComposerError::NotFoundHomeDir)?
+        .join(CONFIG_DIR))
+}
+
 #[derive(Debug, Deserialize, Clone, Serialize, Default)]
 pub struct GlobalConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -45,10 +54,12 @@ impl GlobalConfig {
             "repo.packagist" => {
                 if !unset {
                     if let (Some(value1), Some(value2)) = (value1, value2) {
-                        self.set_repo(value1, value2)?;
+                        self.repositories
+                            .get_or_insert_with(Repositories::default)
+                            .set(PACKAGIST, value1, value2);
                     }
-                } else {
-                    self.repositories = None;
+                } else if let Some(repositories) = &mut self.repositories {
+                    repositories.remove(PACKAGIST);
                 }
             }
             _ => todo!(),
@@ -57,29 +68,6 @@ impl GlobalConfig {
         Ok(())
     }
 
-    fn set_repo(&mut self, value1: &str, value2: &str) -> Result<(), ComposerError> {
-        let repo = self.repositories.take();
-
-        self.repositories = match repo {
-            Some(mut repo) => {
-                repo.packagist._type = value1.to_owned();
-                repo.packagist.url = value2.to_owned();
-                Some(repo)
-            }
-            None => {
-                let repo = Repositories {
-                    packagist: Packagist {
-                        _type: value1.to_owned(),
-                        url: value2.to_owned(),
-                    },
-                };
-                Some(repo)
-            }
-        };
-
-        Ok(())
-    }
-
     pub fn save(&self) -> Result<(), ComposerError> {
         let config_dir = home_dir()
             .ok_or(ComposerError::NotFoundHomeDir)?
@@ -95,14 +83,247 @@ impl GlobalConfig {
     }
 }
 
-#[derive(Debug, Deserialize, Clone, Serialize)]
+/// the name of the default repository in the config
+pub(crate) const PACKAGIST: &str = "packagist";
+
+/// the `repositories` of composer.json or of the global config, by priority
+///
+/// Composer accepts a list of repositories, or an object whose keys name them.
+/// `{"packagist.org": false}` disables packagist, and a repository named
+/// `packagist` replaces it, like a mirror.
+#[derive(Debug, Clone, Default, PartialEq)]
 pub(crate) struct Repositories {
-    pub(crate) packagist: Packagist,
+    pub(crate) entries: Vec<RepositoryEntry>,
+    /// written back as an object
+    named: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum RepositoryEntry {
+    Repository {
+        name: Option<String>,
+        config: RepositoryConfig,
+    },
+    /// `"packagist.org": false`
+    Disabled(String),
 }
 
-#[derive(Debug, Deserialize, Clone, Serialize)]
-pub(crate) struct Packagist {
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub(crate) struct RepositoryConfig {
     #[serde(rename = "type")]
     pub(crate) _type: String,
+    /// none for `package` repositories
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub(crate) url: String,
+    /// with `false`, the packages found here are also looked up in the next repositories
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub(crate) canonical: bool,
+    /// only these packages are looked up here, `*` is a wildcard
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) only: Vec<String>,
+    /// these packages are never looked up here
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) exclude: Vec<String>,
+    /// the options of the other repository types
+    #[serde(flatten)]
+    pub(crate) options: serde_json::Map<String, Value>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+impl RepositoryConfig {
+    pub(crate) fn new(_type: &str, url: &str) -> Self {
+        RepositoryConfig {
+            _type: _type.to_owned(),
+            url: url.to_owned(),
+            canonical: true,
+            only: Vec::new(),
+            exclude: Vec::new(),
+            options: serde_json::Map::new(),
+        }
+    }
+}
+
+impl Repositories {
+    /// add or replace the repository called `name`
+    pub(crate) fn set(&mut self, name: &str, _type: &str, url: &str) {
+        let config = RepositoryConfig::new(_type, url);
+        for entry in self.entries.iter_mut() {
+            if let RepositoryEntry::Repository {
+                name: Some(n),
+                config: c,
+            } = entry
+            {
+                if n == name {
+                    *c = config;
+                    return;
+                }
+            }
+        }
+        self.entries.push(RepositoryEntry::Repository {
+            name: Some(name.to_owned()),
+            config,
+        });
+    }
+
+    /// remove the repository called `name`
+    pub(crate) fn remove(&mut self, name: &str) {
+        self.entries.retain(|entry| match entry {
+            RepositoryEntry::Repository { name: n, .. } => n.as_deref() != Some(name),
+            RepositoryEntry::Disabled(n) => n != name,
+        });
+    }
+
+    /// is packagist disabled by `"packagist.org": false`
+    pub(crate) fn packagist_disabled(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| matches!(entry, RepositoryEntry::Disabled(n) if is_packagist(n)))
+    }
+
+    /// the repository replacing packagist
+    pub(crate) fn packagist(&self) -> Option<&RepositoryConfig> {
+        self.entries.iter().find_map(|entry| match entry {
+            RepositoryEntry::Repository {
+                name: Some(n),
+                config,
+            } if is_packagist(n) => Some(config),
+            _ => None,
+        })
+    }
+
+    /// the repositories, packagist and its mirror excluded
+    pub(crate) fn configs(&self) -> impl Iterator<Item = &RepositoryConfig> {
+        self.entries.iter().filter_map(|entry| match entry {
+            RepositoryEntry::Repository { name, config }
+                if !name.as_deref().is_some_and(is_packagist) =>
+            {
+                Some(config)
+            }
+            _ => None,
+        })
+    }
+}
+
+fn is_packagist(name: &str) -> bool {
+    name == PACKAGIST || name == "packagist.org"
+}
+
+impl<'de> Deserialize<'de> for Repositories {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        let entry = |name: Option<String>, value: Value| -> Result<RepositoryEntry, D::Error> {
+            match (name, value) {
+                (Some(name), Value::Bool(false)) => Ok(RepositoryEntry::Disabled(name)),
+                (name, value) => Ok(RepositoryEntry::Repository {
+                    name,
+                    config: serde_json::from_value(value).map_err(D::Error::custom)?,
+                }),
+            }
+        };
+
+        let mut entries = Vec::new();
+        match Value::deserialize(deserializer)? {
+            Value::Object(map) => {
+                for (name, value) in map {
+                    entries.push(entry(Some(name), value)?);
+                }
+                return Ok(Repositories {
+                    entries,
+                    named: true,
+                });
+            }
+            Value::Array(list) => {
+                for value in list {
+                    // `{"packagist.org": false}` in a list
+                    let disabled = value.as_object().and_then(|map| match map.iter().next() {
+                        Some((name, Value::Bool(false))) if map.len() == 1 => Some(name.clone()),
+                        _ => None,
+                    });
+                    match (disabled, value) {
+                        (Some(name), _) => entries.push(RepositoryEntry::Disabled(name)),
+                        (None, Value::Object(mut map)) => {
+                            let name = match map.remove("name") {
+                                Some(Value::String(name)) => Some(name),
+                                _ => None,
+                            };
+                            entries.push(entry(name, Value::Object(map))?);
+                        }
+                        (None, value) => entries.push(entry(None, value)?),
+                    }
+                }
+            }
+            _ => return Err(D::Error::custom("repositories must be a list or an object")),
+        }
+
+        Ok(Repositories {
+            entries,
+            named: false,
+        })
+    }
+}
+
+impl Serialize for Repositories {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::{SerializeMap, SerializeSeq};
+
+        let all_named = self.entries.iter().all(|entry| {
+            matches!(
+                entry,
+                RepositoryEntry::Repository { name: Some(_), .. } | RepositoryEntry::Disabled(_)
+            )
+        });
+        if self.named && all_named {
+            let mut map = serializer.serialize_map(Some(self.entries.len()))?;
+            for entry in self.entries.iter() {
+                match entry {
+                    RepositoryEntry::Repository { name, config } => {
+                        map.serialize_entry(name.as_deref().unwrap_or_default(), config)?
+                    }
+                    RepositoryEntry::Disabled(name) => map.serialize_entry(name, &false)?,
+                }
+            }
+            return map.end();
+        }
+
+        let mut seq = serializer.serialize_seq(Some(self.entries.len()))?;
+        for entry in self.entries.iter() {
+            match entry {
+                RepositoryEntry::Repository {
+                    name: Some(name),
+                    config,
+                } => {
+                    // a name is kept as the `name` key of the entry
+                    let mut value =
+                        serde_json::to_value(config).map_err(serde::ser::Error::custom)?;
+                    if let Value::Object(map) = &mut value {
+                        map.insert("name".to_owned(), Value::String(name.clone()));
+                    }
+                    seq.serialize_element(&value)?
+                }
+                RepositoryEntry::Repository { name: None, config } => {
+                    seq.serialize_element(config)?
+                }
+                RepositoryEntry::Disabled(name) => {
+                    let mut map = serde_json::Map::new();
+                    map.insert(name.clone(), Value::Bool(false));
+                    seq.serialize_element(&map)?
+                }
+            }
+        }
+        seq.end()
+    }
 }
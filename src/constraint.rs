@@ -0,0 +1,949 @@
+//! parse and match composer version constraints
+//!
+//! This follows `Composer\Semver\VersionParser`: versions are normalized to
+//! `major.minor.patch.build[-stability]`, `dev-<branch>` or `<numeric>.9999999-dev`,
+//! and constraints are parsed into `Single` comparisons joined by `And` / `Or`.
+//! see https://getcomposer.org/doc/articles/versions.md
+
+use std::{cmp::Ordering, sync::LazyLock};
+
+use regex::{Captures, Regex};
+
+use crate::error::ComposerError;
+
+const MODIFIER: &str =
+    r"[._-]?(?:(stable|beta|b|RC|alpha|a|patch|pl|p)((?:[.-]?\d+)*)?)?([.-]?dev)?";
+
+static CLASSICAL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(
+        r"(?i)^v?(\d{{1,5}})(\.\d+)?(\.\d+)?(\.\d+)?{MODIFIER}$"
+    ))
+    .unwrap()
+});
+static DATE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(
+        r"(?i)^v?(\d{{4}}(?:[.:-]?\d{{2}}){{1,6}}(?:[.:-]?\d{{1,3}}){{0,2}}){MODIFIER}$"
+    ))
+    .unwrap()
+});
+static BRANCH: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^v?(\d+)(\.(?:\d+|[xX*]))?(\.(?:\d+|[xX*]))?(\.(?:\d+|[xX*]))?$").unwrap()
+});
+static DEV_SUFFIX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)^(.*?)[.-]?dev$").unwrap());
+static ALIAS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^([^,\s]+) +as +([^,\s]+)$").unwrap());
+static STABILITY_FLAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^([^,\s]*?)@(stable|RC|beta|alpha|dev)$").unwrap());
+static STABILITY_SUFFIX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(&format!(r"(?i){MODIFIER}(?:\+.*)?$")).unwrap());
+static REFERENCE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^(dev-[^,\s@]+?|[^,\s@]+?\.x-dev)#.+$").unwrap());
+static ANY: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(v)?[xX*](\.[xX*])*$").unwrap());
+static VERSION: LazyLock<String> = LazyLock::new(|| {
+    format!(r"v?(\d+)(?:\.(\d+))?(?:\.(\d+))?(?:\.(\d+))?{MODIFIER}(?:\+[^\s]+)?")
+});
+static TILDE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(&format!(r"(?i)^~{}$", *VERSION)).unwrap());
+static CARET: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(&format!(r"(?i)^\^{}$", *VERSION)).unwrap());
+static PLAIN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(&format!(r"(?i)^{}$", *VERSION)).unwrap());
+static WILDCARD: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^v?(\d+)(?:\.(\d+))?(?:\.(\d+))?(?:\.[xX*])+$").unwrap());
+static HYPHEN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(\S+) +- +(\S+)$").unwrap());
+static OPERATOR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(<>|!=|>=?|<=?|==?)?\s*(.*)$").unwrap());
+static STABLE_MODIFIER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(&format!(r"-{MODIFIER}$")).unwrap());
+
+/// stability of a version, from the least to the most stable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub(crate) enum Stability {
+    Dev,
+    Alpha,
+    Beta,
+    RC,
+    #[default]
+    Stable,
+}
+
+impl Stability {
+    /// the stability of a (normalized or pretty) version
+    pub fn of(version: &str) -> Stability {
+        let version = version.split('#').next().unwrap_or_default().to_lowercase();
+        if version.starts_with("dev-") || version.ends_with("-dev") {
+            return Stability::Dev;
+        }
+
+        if let Some(caps) = STABILITY_SUFFIX.captures(&version) {
+            if caps.get(3).is_some_and(|m| !m.as_str().is_empty()) {
+                return Stability::Dev;
+            }
+            match caps.get(1).map(|m| m.as_str()) {
+                Some("beta") | Some("b") => return Stability::Beta,
+                Some("alpha") | Some("a") => return Stability::Alpha,
+                Some("rc") => return Stability::RC,
+                _ => {}
+            }
+        }
+
+        Stability::Stable
+    }
+
+    pub fn parse(name: &str) -> Result<Stability, ComposerError> {
+        match name.to_lowercase().as_str() {
+            "dev" => Ok(Stability::Dev),
+            "alpha" => Ok(Stability::Alpha),
+            "beta" => Ok(Stability::Beta),
+            "rc" => Ok(Stability::RC),
+            "stable" => Ok(Stability::Stable),
+            _ => Err(ComposerError::InvalidStability(name.to_owned())),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stability::Dev => "dev",
+            Stability::Alpha => "alpha",
+            Stability::Beta => "beta",
+            Stability::RC => "RC",
+            Stability::Stable => "stable",
+        }
+    }
+
+    /// the number composer writes in the `stability-flags` of composer.lock
+    pub fn priority(&self) -> u8 {
+        match self {
+            Stability::Dev => 20,
+            Stability::Alpha => 15,
+            Stability::Beta => 10,
+            Stability::RC => 5,
+            Stability::Stable => 0,
+        }
+    }
+
+    /// the stability a root requirement asks for, with an explicit `@beta` flag or
+    /// an unstable version like `2.0.0-RC1`, `None` when `minimum` already allows it
+    pub fn flag(constraint: &str, minimum: Stability) -> Option<Stability> {
+        let mut constraints = Vec::new();
+        for part in split_or(constraint) {
+            constraints.extend(split_and(part));
+        }
+
+        // explicit stability flags, the most unstable wins
+        let explicit = constraints
+            .iter()
+            .filter_map(|c| STABILITY_FLAG.captures(c))
+            .filter_map(|caps| Stability::parse(caps.get(2).unwrap().as_str()).ok())
+            .min();
+        if explicit.is_some() {
+            return explicit;
+        }
+
+        // infer the flag from unstable versions
+        constraints
+            .iter()
+            .map(|c| match ALIAS.captures(c) {
+                Some(caps) => caps.get(1).unwrap().as_str().to_owned(),
+                None => c.to_owned(),
+            })
+            .filter(|c| !c.contains(['@', ',', ' ']))
+            .map(|c| Stability::of(&c))
+            .filter(|stability| *stability < minimum)
+            .min()
+    }
+}
+
+/// split an inline alias like `dev-main as 1.5.0` into the aliased version and the alias
+pub(crate) fn inline_alias(constraint: &str) -> Option<(&str, &str)> {
+    let caps = ALIAS.captures(constraint.trim())?;
+    let version = caps.get(1).unwrap().as_str();
+    let version = version.split('#').next().unwrap_or_default();
+    Some((version, caps.get(2).unwrap().as_str()))
+}
+
+/// normalize a version, `v1.2` is `1.2.0.0`, `2.x-dev` is `2.9999999.9999999.9999999-dev`
+pub(crate) fn normalize(version: &str) -> Result<String, ComposerError> {
+    let orig = version.trim();
+    let mut version = orig;
+
+    // strip off aliasing
+    if let Some(caps) = ALIAS.captures(version) {
+        version = caps.get(1).unwrap().as_str();
+    }
+
+    // strip off stability flag
+    if let Some(caps) = STABILITY_FLAG.captures(version) {
+        version = caps.get(1).unwrap().as_str();
+    }
+
+    if matches!(version, "master" | "trunk" | "default") {
+        return Ok(format!("dev-{}", version));
+    }
+
+    // if requirement is branch-like, use full name
+    if version.len() >= 4 && version[..4].eq_ignore_ascii_case("dev-") {
+        return Ok(format!("dev-{}", &version[4..]));
+    }
+
+    // strip off build metadata
+    if let Some((v, _)) = version.split_once('+') {
+        if !v.is_empty() && !v.contains([',', ' ']) {
+            version = v;
+        }
+    }
+
+    let matched = if let Some(caps) = CLASSICAL.captures(version) {
+        let mut normalized = caps.get(1).unwrap().as_str().to_owned();
+        for i in 2..=4 {
+            normalized.push_str(caps.get(i).map_or(".0", |m| m.as_str()));
+        }
+        Some((normalized, caps, 5))
+    } else if let Some(caps) = DATE.captures(version) {
+        let normalized = caps
+            .get(1)
+            .unwrap()
+            .as_str()
+            .replace(|c: char| !c.is_ascii_digit(), ".");
+        Some((normalized, caps, 2))
+    } else {
+        None
+    };
+
+    if let Some((mut normalized, caps, index)) = matched {
+        if let Some(stability) = caps.get(index).map(|m| m.as_str().to_lowercase()) {
+            if stability == "stable" {
+                return Ok(normalized);
+            }
+            normalized.push('-');
+            normalized.push_str(expand_stability(&stability));
+            if let Some(num) = caps.get(index + 1) {
+                normalized.push_str(num.as_str().trim_start_matches(['.', '-']));
+            }
+        }
+        if caps.get(index + 2).is_some_and(|m| !m.as_str().is_empty()) {
+            normalized.push_str("-dev");
+        }
+        return Ok(normalized);
+    }
+
+    // match dev branches
+    if let Some(caps) = DEV_SUFFIX.captures(version) {
+        let normalized = normalize_branch(caps.get(1).unwrap().as_str());
+        // a branch ending with -dev is only valid if it is numeric
+        if !normalized.starts_with("dev-") {
+            return Ok(normalized);
+        }
+    }
+
+    Err(ComposerError::InvalidVersion(orig.to_owned()))
+}
+
+/// normalize a branch name, `2.1.x` is `2.1.9999999.9999999-dev`, `main` is `dev-main`
+pub(crate) fn normalize_branch(name: &str) -> String {
+    let name = name.trim();
+
+    if let Some(caps) = BRANCH.captures(name) {
+        let mut version = String::new();
+        for i in 1..5 {
+            match caps.get(i) {
+                Some(m) => version.push_str(&m.as_str().replace(['*', 'X'], "x")),
+                None => version.push_str(".x"),
+            }
+        }
+        return format!("{}-dev", version.replace('x', "9999999"));
+    }
+
+    format!("dev-{}", name)
+}
+
+fn expand_stability(stability: &str) -> &str {
+    match stability {
+        "a" => "alpha",
+        "b" => "beta",
+        "p" | "pl" => "patch",
+        "rc" => "RC",
+        s => s,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Operator {
+    fn parse(op: &str) -> Operator {
+        match op {
+            "<>" | "!=" => Operator::Ne,
+            "<" => Operator::Lt,
+            "<=" => Operator::Le,
+            ">" => Operator::Gt,
+            ">=" => Operator::Ge,
+            _ => Operator::Eq,
+        }
+    }
+}
+
+/// a parsed version constraint, all versions in it are normalized
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Constraint {
+    Any,
+    Single(Operator, String),
+    And(Vec<Constraint>),
+    Or(Vec<Constraint>),
+}
+
+impl Constraint {
+    /// parse a constraint like `^1.2 || >=2.0 <2.5,!=2.3`
+    pub fn parse(constraints: &str) -> Result<Constraint, ComposerError> {
+        let mut constraints = constraints.trim();
+
+        // strip off inline aliasing, `dev-main as 1.0.0` requires dev-main
+        if let Some(caps) = ALIAS.captures(constraints) {
+            constraints = caps.get(1).unwrap().as_str();
+        }
+
+        let mut or = Vec::new();
+        for part in split_or(constraints) {
+            let mut and = Vec::new();
+            for single in split_and(part) {
+                and.extend(parse_single(&single)?);
+            }
+            or.push(if and.len() == 1 {
+                and.pop().unwrap()
+            } else {
+                Constraint::And(and)
+            });
+        }
+
+        if or.is_empty() {
+            return Err(ComposerError::InvalidConstraint(constraints.to_owned()));
+        }
+
+        Ok(if or.len() == 1 {
+            or.pop().unwrap()
+        } else {
+            Constraint::Or(or)
+        })
+    }
+
+    /// does the normalized version satisfy the constraint
+    pub fn matches(&self, normalized: &str) -> bool {
+        match self {
+            Constraint::Any => true,
+            Constraint::Single(op, version) => version_compare(normalized, version, *op),
+            Constraint::And(list) => list.iter().all(|c| c.matches(normalized)),
+            Constraint::Or(list) => list.iter().any(|c| c.matches(normalized)),
+        }
+    }
+}
+
+/// split on `||` and `|`
+fn split_or(constraints: &str) -> Vec<&str> {
+    constraints
+        .split('|')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// split on commas and spaces, keep `>= 1.0`, `1.0 - 2.0` and `dev-main as 1.0` together
+fn split_and(constraints: &str) -> Vec<String> {
+    let tokens: Vec<&str> = constraints
+        .split([',', ' ', '\t'])
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    let mut res: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let mut token = tokens[i].to_owned();
+        // an operator alone belongs to the next version
+        while token.chars().all(|c| "<>=!~^".contains(c)) && i + 1 < tokens.len() {
+            i += 1;
+            token.push_str(tokens[i]);
+        }
+        // hyphen range and inline alias
+        if i + 2 < tokens.len() && (tokens[i + 1] == "-" || tokens[i + 1] == "as") {
+            token = format!("{} {} {}", token, tokens[i + 1], tokens[i + 2]);
+            i += 2;
+        }
+        res.push(token);
+        i += 1;
+    }
+    res
+}
+
+/// the version numbers of a `VERSION` match, the count of given numbers, and whether
+/// a stability or dev modifier is given
+fn version_parts(caps: &Captures) -> ([u64; 4], usize, bool, bool) {
+    let mut parts = [0; 4];
+    let mut count = 0;
+    for (i, part) in parts.iter_mut().enumerate() {
+        if let Some(m) = caps.get(i + 1) {
+            *part = m.as_str().parse().unwrap_or(0);
+            count = i + 1;
+        }
+    }
+    let stability = caps.get(5).is_some();
+    let dev = caps.get(7).is_some_and(|m| !m.as_str().is_empty());
+    (parts, count, stability, dev)
+}
+
+/// reset the numbers after `position`, and add `increment` to the one at `position`
+fn manipulate(mut parts: [u64; 4], position: usize, increment: u64) -> String {
+    for i in (1..=4).rev() {
+        if i > position {
+            parts[i - 1] = 0;
+        } else if i == position {
+            parts[i - 1] += increment;
+        }
+    }
+    format!("{}.{}.{}.{}", parts[0], parts[1], parts[2], parts[3])
+}
+
+fn parse_single(constraint: &str) -> Result<Vec<Constraint>, ComposerError> {
+    let mut constraint = constraint;
+    let mut stability_modifier = None;
+
+    if let Some(caps) = ALIAS.captures(constraint) {
+        constraint = caps.get(1).unwrap().as_str();
+    }
+
+    if let Some(caps) = STABILITY_FLAG.captures(constraint) {
+        let version = caps.get(1).unwrap().as_str();
+        constraint = if version.is_empty() { "*" } else { version };
+        let flag = caps.get(2).unwrap().as_str().to_lowercase();
+        if flag != "stable" {
+            stability_modifier = Some(flag);
+        }
+    }
+
+    // get rid of #refs as those are used by composers dev branches
+    if let Some(caps) = REFERENCE.captures(constraint) {
+        constraint = caps.get(1).unwrap().as_str();
+    }
+
+    if let Some(caps) = ANY.captures(constraint) {
+        if caps.get(1).is_some() || caps.get(2).is_some() {
+            return Ok(vec![Constraint::Single(
+                Operator::Ge,
+                "0.0.0.0-dev".to_owned(),
+            )]);
+        }
+        return Ok(vec![Constraint::Any]);
+    }
+
+    // ~1.2 is >=1.2 <2.0, ~1.2.3 is >=1.2.3 <1.3
+    if let Some(caps) = TILDE.captures(constraint) {
+        let (parts, count, stability, dev) = version_parts(&caps);
+        let position = count.max(1);
+        let suffix = if !stability && !dev { "-dev" } else { "" };
+        let low = normalize(&format!("{}{}", &constraint[1..], suffix))?;
+        let high = manipulate(parts, (position - 1).max(1), 1) + "-dev";
+        return Ok(vec![
+            Constraint::Single(Operator::Ge, low),
+            Constraint::Single(Operator::Lt, high),
+        ]);
+    }
+
+    // ^1.2.3 is >=1.2.3 <2.0, ^0.3 is >=0.3 <0.4
+    if let Some(caps) = CARET.captures(constraint) {
+        let (parts, count, stability, dev) = version_parts(&caps);
+        let position = if parts[0] != 0 || count < 2 {
+            1
+        } else if parts[1] != 0 || count < 3 {
+            2
+        } else {
+            3
+        };
+        let suffix = if !stability && !dev { "-dev" } else { "" };
+        let low = normalize(&format!("{}{}", &constraint[1..], suffix))?;
+        let high = manipulate(parts, position, 1) + "-dev";
+        return Ok(vec![
+            Constraint::Single(Operator::Ge, low),
+            Constraint::Single(Operator::Lt, high),
+        ]);
+    }
+
+    // 1.2.* is >=1.2 <1.3
+    if let Some(caps) = WILDCARD.captures(constraint) {
+        let (parts, count, _, _) = version_parts(&caps);
+        let position = count.max(1);
+        let low = manipulate(parts, position, 0) + "-dev";
+        let high = manipulate(parts, position, 1) + "-dev";
+        if low == "0.0.0.0-dev" {
+            return Ok(vec![Constraint::Single(Operator::Lt, high)]);
+        }
+        return Ok(vec![
+            Constraint::Single(Operator::Ge, low),
+            Constraint::Single(Operator::Lt, high),
+        ]);
+    }
+
+    // 1.0 - 2.0 is >=1.0 <2.1, 1.0.0 - 2.1.0 is >=1.0.0 <=2.1.0
+    if let Some(caps) = HYPHEN.captures(constraint) {
+        let from = caps.get(1).unwrap().as_str();
+        let to = caps.get(2).unwrap().as_str();
+        if let (Some(from_caps), Some(to_caps)) = (PLAIN.captures(from), PLAIN.captures(to)) {
+            let (_, _, from_stability, from_dev) = version_parts(&from_caps);
+            let suffix = if !from_stability && !from_dev {
+                "-dev"
+            } else {
+                ""
+            };
+            let low = normalize(from)? + suffix;
+
+            let (to_parts, to_count, to_stability, to_dev) = version_parts(&to_caps);
+            let high = if to_count >= 3 || to_stability || to_dev {
+                Constraint::Single(Operator::Le, normalize(to)?)
+            } else {
+                let position = if to_count < 2 { 1 } else { 2 };
+                Constraint::Single(Operator::Lt, manipulate(to_parts, position, 1) + "-dev")
+            };
+            return Ok(vec![Constraint::Single(Operator::Ge, low), high]);
+        }
+    }
+
+    // basic comparators
+    if let Some(caps) = OPERATOR.captures(constraint) {
+        let op_str = caps.get(1).map_or("", |m| m.as_str());
+        let version_str = caps.get(2).unwrap().as_str();
+        let mut version = match normalize(version_str) {
+            Ok(v) => v,
+            // recover from an invalid constraint like foobar-dev which should be dev-foobar
+            Err(e) => match version_str.strip_suffix("-dev") {
+                Some(branch)
+                    if version_str
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "-./".contains(c)) =>
+                {
+                    normalize(&format!("dev-{}", branch))?
+                }
+                _ => return Err(e),
+            },
+        };
+
+        let op = Operator::parse(op_str);
+        match stability_modifier {
+            Some(flag) if op != Operator::Eq && Stability::of(&version) == Stability::Stable => {
+                version.push('-');
+                version.push_str(&flag);
+            }
+            _ => {
+                if (op == Operator::Lt || op == Operator::Ge)
+                    && !STABLE_MODIFIER.is_match(&version_str.to_lowercase())
+                    && !version_str.starts_with("dev-")
+                {
+                    version.push_str("-dev");
+                }
+            }
+        }
+
+        return Ok(vec![Constraint::Single(op, version)]);
+    }
+
+    Err(ComposerError::InvalidConstraint(constraint.to_owned()))
+}
+
+/// compare two normalized versions, dev branches only equal themselves
+fn version_compare(a: &str, b: &str, op: Operator) -> bool {
+    let a_branch = a.starts_with("dev-");
+    let b_branch = b.starts_with("dev-");
+
+    if op == Operator::Ne && (a_branch || b_branch) {
+        return a != b;
+    }
+    if a_branch && b_branch {
+        return op == Operator::Eq && a == b;
+    }
+    // dev branches never match a numeric constraint
+    if a_branch || b_branch {
+        return false;
+    }
+
+    let ordering = compare(a, b);
+    match op {
+        Operator::Eq => ordering == Ordering::Equal,
+        Operator::Ne => ordering != Ordering::Equal,
+        Operator::Lt => ordering == Ordering::Less,
+        Operator::Le => ordering != Ordering::Greater,
+        Operator::Gt => ordering == Ordering::Greater,
+        Operator::Ge => ordering != Ordering::Less,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Part<'a> {
+    Number(u64),
+    Special(&'a str),
+}
+
+/// split like php's `version_compare`, `1.0.0.0-beta2` is `1 0 0 0 beta 2`
+fn canonicalize(version: &str) -> Vec<Part<'_>> {
+    let mut parts = Vec::new();
+    let mut start = None;
+    let mut digits = false;
+    let bytes = version.as_bytes();
+
+    for i in 0..=bytes.len() {
+        let c = bytes.get(i).copied();
+        let is_sep = matches!(c, None | Some(b'.') | Some(b'-') | Some(b'_') | Some(b'+'));
+        let is_digit = c.is_some_and(|c| c.is_ascii_digit());
+        if let Some(s) = start {
+            if is_sep || is_digit != digits {
+                let part = &version[s..i];
+                parts.push(if digits {
+                    Part::Number(part.parse().unwrap_or(u64::MAX))
+                } else {
+                    Part::Special(part)
+                });
+                start = None;
+            }
+        }
+        if !is_sep && start.is_none() {
+            start = Some(i);
+            digits = is_digit;
+        }
+    }
+
+    parts
+}
+
+/// order of special forms in php's `version_compare`, numbers are `#`
+fn special_order(form: &str) -> i32 {
+    const FORMS: [(&str, i32); 10] = [
+        ("dev", 0),
+        ("alpha", 1),
+        ("a", 1),
+        ("beta", 2),
+        ("b", 2),
+        ("RC", 3),
+        ("rc", 3),
+        ("#", 4),
+        ("pl", 5),
+        ("p", 5),
+    ];
+    FORMS
+        .iter()
+        .find(|(name, _)| form.starts_with(name))
+        .map_or(-1, |(_, order)| *order)
+}
+
+/// php's `version_compare`
+pub(crate) fn compare(a: &str, b: &str) -> Ordering {
+    let a = canonicalize(a);
+    let b = canonicalize(b);
+
+    for (x, y) in a.iter().zip(b.iter()) {
+        let ordering = match (x, y) {
+            (Part::Number(x), Part::Number(y)) => x.cmp(y),
+            (Part::Special(x), Part::Special(y)) => special_order(x).cmp(&special_order(y)),
+            (Part::Number(_), Part::Special(y)) => special_order("#").cmp(&special_order(y)),
+            (Part::Special(x), Part::Number(_)) => special_order(x).cmp(&special_order("#")),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    let rest = |part: Option<&Part>| match part {
+        Some(Part::Number(_)) => Ordering::Greater,
+        Some(Part::Special(x)) => special_order(x).cmp(&special_order("#")),
+        None => Ordering::Equal,
+    };
+    match a.len().cmp(&b.len()) {
+        Ordering::Greater => rest(a.get(b.len())),
+        Ordering::Less => rest(b.get(a.len())).reverse(),
+        Ordering::Equal => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn check(constraint: &str, version: &str) -> bool {
+        Constraint::parse(constraint)
+            .unwrap()
+            .matches(&normalize(version).unwrap())
+    }
+
+    #[test]
+    fn test_normalize() {
+        let cases = [
+            ("1.0.0", "1.0.0.0"),
+            ("1.2.3.4", "1.2.3.4"),
+            ("v1.0", "1.0.0.0"),
+            ("1.0.0RC1dev", "1.0.0.0-RC1-dev"),
+            ("1.0.0-rC15-dev", "1.0.0.0-RC15-dev"),
+            ("1.0.0.RC.15-dev", "1.0.0.0-RC15-dev"),
+            ("1.0.0-beta.5+foo", "1.0.0.0-beta5"),
+            ("1.0.0-b1", "1.0.0.0-beta1"),
+            ("1.0.0-alpha3.1", "1.0.0.0-alpha3.1"),
+            ("1.0.0-pl3", "1.0.0.0-patch3"),
+            ("1.0.0-stable", "1.0.0.0"),
+            ("1.0-dev", "1.0.0.0-dev"),
+            ("20100102", "20100102"),
+            ("2010.01.02", "2010.01.02.0"),
+            ("dev-master", "dev-master"),
+            ("master", "dev-master"),
+            ("dev-feature/foo", "dev-feature/foo"),
+            ("1.x-dev", "1.9999999.9999999.9999999-dev"),
+            ("2.1.x-dev", "2.1.9999999.9999999-dev"),
+            ("1.0.0@beta", "1.0.0.0"),
+            ("dev-main as 1.0.0", "dev-main"),
+        ];
+        for (version, expected) in cases {
+            assert_eq!(normalize(version).unwrap(), expected, "{}", version);
+        }
+
+        assert!(normalize("foo").is_err());
+        assert!(normalize("1.0.0 foo").is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        use Operator::*;
+        let single = |op, v: &str| Constraint::Single(op, v.to_owned());
+        let range =
+            |low: &str, high: &str| Constraint::And(vec![single(Ge, low), single(Lt, high)]);
+
+        let cases = [
+            ("*", Constraint::Any),
+            ("v*.*", single(Ge, "0.0.0.0-dev")),
+            ("1.0.0", single(Eq, "1.0.0.0")),
+            (">=1.0", single(Ge, "1.0.0.0-dev")),
+            ("<1.2-beta", single(Lt, "1.2.0.0-beta")),
+            ("!=1.5", single(Ne, "1.5.0.0")),
+            (">= 1.0", single(Ge, "1.0.0.0-dev")),
+            ("~1.2", range("1.2.0.0-dev", "2.0.0.0-dev")),
+            ("~1.2.3", range("1.2.3.0-dev", "1.3.0.0-dev")),
+            ("~1", range("1.0.0.0-dev", "2.0.0.0-dev")),
+            ("~1.2-beta", range("1.2.0.0-beta", "2.0.0.0-dev")),
+            ("^1.2.3", range("1.2.3.0-dev", "2.0.0.0-dev")),
+            ("^0.3", range("0.3.0.0-dev", "0.4.0.0-dev")),
+            ("^0.0.3", range("0.0.3.0-dev", "0.0.4.0-dev")),
+            ("^2.0@beta", range("2.0.0.0-dev", "3.0.0.0-dev")),
+            ("1.2.*", range("1.2.0.0-dev", "1.3.0.0-dev")),
+            ("1.x", range("1.0.0.0-dev", "2.0.0.0-dev")),
+            ("0.*", single(Lt, "1.0.0.0-dev")),
+            ("1.0 - 2.0", range("1.0.0.0-dev", "2.1.0.0-dev")),
+            (
+                "1.0.0 - 2.1.0",
+                Constraint::And(vec![single(Ge, "1.0.0.0-dev"), single(Le, "2.1.0.0")]),
+            ),
+            ("dev-main", single(Eq, "dev-main")),
+            ("dev-main#abc123", single(Eq, "dev-main")),
+            ("1.0.x-dev", single(Eq, "1.0.9999999.9999999-dev")),
+            ("feature-dev", single(Eq, "dev-feature")),
+            ("dev-main as 1.5.0", single(Eq, "dev-main")),
+            (
+                ">=1.0 <2.0,!=1.5",
+                Constraint::And(vec![
+                    single(Ge, "1.0.0.0-dev"),
+                    single(Lt, "2.0.0.0-dev"),
+                    single(Ne, "1.5.0.0"),
+                ]),
+            ),
+            (
+                "^1.0 || ^2.0",
+                Constraint::Or(vec![
+                    range("1.0.0.0-dev", "2.0.0.0-dev"),
+                    range("2.0.0.0-dev", "3.0.0.0-dev"),
+                ]),
+            ),
+            (">=1.0@dev", single(Ge, "1.0.0.0-dev")),
+        ];
+        for (constraint, expected) in cases {
+            assert_eq!(
+                Constraint::parse(constraint).unwrap(),
+                expected,
+                "{}",
+                constraint
+            );
+        }
+
+        assert!(Constraint::parse("").is_err());
+        assert!(Constraint::parse(">=foo").is_err());
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(check("^7.0| ^8.0", "7.2.3"));
+        assert!(check("^7.0| ^8.0", "8.2.3"));
+        assert!(!check("^7.0| ^8.0", "9.2.3"));
+        assert!(!check("^7.0|| ^8.0", "9.2.3"));
+        assert!(check("^7.0| ^8.0", "8.0"));
+        assert!(check(">=7.4", "8.0"));
+        assert!(!check(">=8.1", "8.0"));
+        assert!(check("5.1.0-RC1", "5.1.0-RC1"));
+
+        // unstable versions are filtered by the stability rules, not by the constraint
+        assert!(check("^1.0", "1.1.0-beta1"));
+        assert!(check("^2.0@beta", "2.1.0-beta1"));
+        assert!(!check(">=1.0 <2.0,!=1.5", "1.5"));
+        assert!(check(">=1.0 <2.0,!=1.5", "1.6"));
+        assert!(check("dev-main", "dev-main"));
+        assert!(!check("dev-main", "dev-master"));
+        assert!(!check(">=1.0", "dev-main"));
+        assert!(check("*", "dev-main"));
+        assert!(check("!=1.0", "dev-main"));
+        assert!(check("2.x-dev", "2.x-dev"));
+        assert!(check("^2.0", "2.x-dev"));
+        assert!(check("1.0.0 - 2.1.0", "2.1.0"));
+        assert!(!check("1.0.0 - 2.1.0", "2.1.1"));
+        assert!(check("1.0 - 2.0", "2.0.9"));
+    }
+
+    #[test]
+    fn test_stability() {
+        assert_eq!(Stability::of("1.0.0"), Stability::Stable);
+        assert_eq!(Stability::of("1.0.0.0-beta2"), Stability::Beta);
+        assert_eq!(Stability::of("1.0.0-b2"), Stability::Beta);
+        assert_eq!(Stability::of("1.0.0-alpha"), Stability::Alpha);
+        assert_eq!(Stability::of("1.0.0-RC1"), Stability::RC);
+        assert_eq!(Stability::of("1.0.0-patch1"), Stability::Stable);
+        assert_eq!(Stability::of("dev-main"), Stability::Dev);
+        assert_eq!(Stability::of("1.x-dev"), Stability::Dev);
+        assert_eq!(Stability::of("dev-main#abc"), Stability::Dev);
+        assert!(Stability::Dev < Stability::Alpha && Stability::RC < Stability::Stable);
+
+        let stable = Stability::Stable;
+        assert_eq!(Stability::flag("^2.0@beta", stable), Some(Stability::Beta));
+        assert_eq!(Stability::flag("@dev", stable), Some(Stability::Dev));
+        assert_eq!(
+            Stability::flag("^1.0@RC || ^2.0@alpha", stable),
+            Some(Stability::Alpha)
+        );
+        assert_eq!(
+            Stability::flag("^1.0@stable", Stability::Dev),
+            Some(Stability::Stable)
+        );
+        assert_eq!(
+            Stability::flag("2.0.0-beta1", stable),
+            Some(Stability::Beta)
+        );
+        assert_eq!(Stability::flag("dev-main", stable), Some(Stability::Dev));
+        assert_eq!(
+            Stability::flag("dev-main as 1.0.0", stable),
+            Some(Stability::Dev)
+        );
+        assert_eq!(Stability::flag("2.0.0-beta1", Stability::Dev), None);
+        assert_eq!(Stability::flag("^2.0", stable), None);
+    }
+
+    #[test]
+    fn test_inline_alias() {
+        assert_eq!(
+            inline_alias("dev-main as 1.5.0"),
+            Some(("dev-main", "1.5.0"))
+        );
+        assert_eq!(
+            inline_alias("dev-main#abc123 as 1.0.x-dev"),
+            Some(("dev-main", "1.0.x-dev"))
+        );
+        assert_eq!(inline_alias("^1.5"), None);
+        assert_eq!(inline_alias(">=1.0 <2.0"), None);
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(compare("1.0.0.0", "1.0.0.0"), Ordering::Equal);
+        assert_eq!(compare("1.0.0.0-dev", "1.0.0.0-alpha1"), Ordering::Less);
+        assert_eq!(compare("1.0.0.0-alpha1", "1.0.0.0-beta1"), Ordering::Less);
+        assert_eq!(compare("1.0.0.0-beta2", "1.0.0.0-RC1"), Ordering::Less);
+        assert_eq!(compare("1.0.0.0-RC1", "1.0.0.0"), Ordering::Less);
+        assert_eq!(compare("1.0.0.0", "1.0.0.0-patch1"), Ordering::Less);
+        assert_eq!(compare("1.0.10.0", "1.0.9.0"), Ordering::Greater);
+        assert_eq!(
+            compare("2.0.0.0-dev", "1.9999999.9999999.9999999-dev"),
+            Ordering::Greater
+        );
+    }
+
+    prop_compose! {
+        fn version()(major in 0u64..20, minor in 0u64..20, patch in 0u64..20) -> (u64, u64, u64) {
+            (major, minor, patch)
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn prop_normalize_is_stable((a, b, c) in version()) {
+            let normalized = normalize(&format!("{a}.{b}.{c}")).unwrap();
+            prop_assert_eq!(&normalized, &format!("{a}.{b}.{c}.0"));
+            prop_assert_eq!(normalize(&normalized).unwrap(), normalized);
+        }
+
+        #[test]
+        fn prop_compare_is_numeric(x in version(), y in version()) {
+            let a = format!("{}.{}.{}", x.0, x.1, x.2);
+            let b = format!("{}.{}.{}", y.0, y.1, y.2);
+            prop_assert_eq!(compare(&normalize(&a).unwrap(), &normalize(&b).unwrap()), x.cmp(&y));
+        }
+
+        #[test]
+        fn prop_caret(c in version(), v in version()) {
+            // ^ allows changes that do not modify the left-most non-zero digit
+            let upper = if c.0 > 0 {
+                (c.0 + 1, 0, 0)
+            } else if c.1 > 0 {
+                (0, c.1 + 1, 0)
+            } else {
+                (0, 0, c.2 + 1)
+            };
+            let expected = v >= c && v < upper;
+            let constraint = format!("^{}.{}.{}", c.0, c.1, c.2);
+            prop_assert_eq!(check(&constraint, &format!("{}.{}.{}", v.0, v.1, v.2)), expected);
+        }
+
+        #[test]
+        fn prop_tilde(c in version(), v in version()) {
+            // ~1.2 is >=1.2 <2.0, ~1.2.3 is >=1.2.3 <1.3.0
+            let minor = format!("~{}.{}", c.0, c.1);
+            let expected = v >= (c.0, c.1, 0) && v < (c.0 + 1, 0, 0);
+            prop_assert_eq!(check(&minor, &format!("{}.{}.{}", v.0, v.1, v.2)), expected);
+
+            let patch = format!("~{}.{}.{}", c.0, c.1, c.2);
+            let expected = v >= c && v < (c.0, c.1 + 1, 0);
+            prop_assert_eq!(check(&patch, &format!("{}.{}.{}", v.0, v.1, v.2)), expected);
+        }
+
+        #[test]
+        fn prop_wildcard(c in version(), v in version()) {
+            let constraint = format!("{}.{}.*", c.0, c.1);
+            let expected = v.0 == c.0 && v.1 == c.1;
+            prop_assert_eq!(check(&constraint, &format!("{}.{}.{}", v.0, v.1, v.2)), expected);
+        }
+
+        #[test]
+        fn prop_hyphen(low in version(), high in version(), v in version()) {
+            let constraint = format!("{}.{}.{} - {}.{}.{}", low.0, low.1, low.2, high.0, high.1, high.2);
+            let expected = v >= low && v <= high;
+            prop_assert_eq!(check(&constraint, &format!("{}.{}.{}", v.0, v.1, v.2)), expected);
+        }
+
+        #[test]
+        fn prop_and_or(a in version(), b in version(), v in version()) {
+            let version = format!("{}.{}.{}", v.0, v.1, v.2);
+            let ge = format!(">={}.{}.{}", a.0, a.1, a.2);
+            let lt = format!("<{}.{}.{}", b.0, b.1, b.2);
+
+            let and = check(&ge, &version) && check(&lt, &version);
+            prop_assert_eq!(check(&format!("{ge} {lt}"), &version), and);
+            prop_assert_eq!(check(&format!("{ge},{lt}"), &version), and);
+
+            let or = check(&ge, &version) || check(&lt, &version);
+            prop_assert_eq!(check(&format!("{ge} || {lt}"), &version), or);
+        }
+    }
+}
@@ -0,0 +1,554 @@
+//! download the dist archives of the locked packages
+//!
+//! Archives are downloaded in parallel, at most `max_parallel` at a time, on one
+//! shared `Http` client. Progress goes to the `ErrWriter`: a single line updated in
+//! place on a terminal, one line per archive otherwise.
+//!
+//! An archive only enters the cache once it is a valid zip matching the
+//! `dist.shasum` of the metadata, and a cached archive failing those checks is
+//! downloaded again.
+//!
+//! Archives are cached by package name, `dist.reference` and repository, so a
+//! retagged release or a new commit on a branch is downloaded again.
+//!
+//! The archive of an artifact repository is read where it is, not downloaded.
+//!
+//! A package with only a git `source` is archived with `git archive` from the
+//! mirror of its repository, unless it is installed as a checkout.
+
+use std::{
+    collections::HashMap,
+    fs::{create_dir_all, read, remove_file, rename, File},
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+};
+
+use reqwest::{
+    header::{HeaderMap, CONTENT_TYPE},
+    StatusCode,
+};
+use tokio::task::JoinSet;
+
+use crate::{
+    constraint::Stability,
+    error::ComposerError,
+    http::Http,
+    io::ErrWriter,
+    package::Version,
+    repository::{origin, vcs},
+};
+
+/// the default number of archives downloaded at the same time, as composer's `max-parallel-http`
+pub(crate) const MAX_PARALLEL_HTTP: usize = 12;
+
+pub(crate) struct Downloader {
+    http: Http,
+    max_parallel: usize,
+    prefer_source: bool,
+}
+
+impl Downloader {
+    pub(crate) fn new(http: Http, max_parallel: usize) -> Self {
+        Self {
+            http,
+            max_parallel: max_parallel.max(1),
+            prefer_source: false,
+        }
+    }
+
+    /// install packages having a git source as a checkout, as composer's `preferred-install: source`
+    pub(crate) fn prefer_source(mut self, prefer_source: bool) -> Self {
+        self.prefer_source = prefer_source;
+        self
+    }
+
+    pub(crate) fn is_prefer_source(&self) -> bool {
+        self.prefer_source
+    }
+
+    /// clone the git repository at `url` into `target`, at the commit `reference`
+    pub(crate) fn checkout(
+        &self,
+        url: &str,
+        reference: &str,
+        target: &Path,
+    ) -> Result<(), ComposerError> {
+        vcs::checkout(url, reference, target, &self.http.credentials())
+    }
+
+    /// download the archives of `items` which are not in `files_dir` yet, and
+    /// return the integrity hash of every archive by package name
+    pub(crate) async fn download<'a>(
+        &self,
+        items: impl Iterator<Item = &'a Version>,
+        files_dir: &Path,
+        stderr: &mut dyn ErrWriter,
+    ) -> Result<HashMap<String, String>, ComposerError> {
+        let items: Vec<&Version> = items.collect();
+        let mut hashes = HashMap::new();
+        let mut queue = Vec::new();
+        for (index, item) in items.iter().enumerate() {
+            // installed from their directory, or as a checkout
+            if item.dist.as_ref().is_some_and(|d| d._type == "path")
+                || is_checkout(item, self.prefer_source)
+                || (item.dist.is_none() && git_source(item).is_none())
+            {
+                continue;
+            }
+            let name = item.name.as_ref().expect("not found name");
+            let path = dist_path(files_dir, item);
+            // installed from the archive of an artifact repository
+            if item.dist.as_ref().is_some_and(|d| d.local) {
+                let checked = match read(&path) {
+                    Ok(content) => verify(&content, item).map(|_| content),
+                    Err(e) => Err(e.to_string()),
+                };
+                match checked {
+                    Ok(content) => {
+                        hashes.insert(name.to_owned(), integrity(&content));
+                    }
+                    Err(reason) => {
+                        let label = format!("{}({})", name, item.version);
+                        stderr.write(&format!("  - Failed to read {}: {}", label, reason));
+                        return Err(ComposerError::InvalidDist(label));
+                    }
+                }
+                continue;
+            }
+            migrate(files_dir, item, &path)?;
+            if path.exists() {
+                let content = read(&path)?;
+                if verify(&content, item).is_ok() {
+                    hashes.insert(name.to_owned(), integrity(&content));
+                    continue;
+                }
+                remove_file(&path)?;
+            }
+            queue.push((index, path));
+        }
+        queue.reverse();
+
+        let total = queue.len();
+        let mut done = 0;
+        let mut tasks = JoinSet::new();
+        loop {
+            while tasks.len() < self.max_parallel {
+                let Some((index, path)) = queue.pop() else {
+                    break;
+                };
+                let item = items[index];
+                if item.dist.is_none() {
+                    let (url, reference) = git_source(item).expect("not found source field");
+                    let (url, reference) = (url.to_owned(), reference.to_owned());
+                    // the mirrors of git repositories are next to `files/` in the cache
+                    let cache_dir = files_dir.parent().unwrap_or(files_dir).to_path_buf();
+                    let auth = self.http.credentials();
+                    tasks.spawn(async move {
+                        let content = tokio::task::spawn_blocking(move || {
+                            vcs::archive(&url, &reference, &cache_dir, &auth)
+                        })
+                        .await??;
+                        Ok((index, path, StatusCode::OK, false, content.into()))
+                    });
+                    continue;
+                }
+                let url = item
+                    .dist
+                    .as_ref()
+                    .expect("not found dist field")
+                    .url
+                    .clone();
+                let http = self.http.clone();
+                tasks.spawn(async move {
+                    let response = http.get(&url, HeaderMap::new()).await?;
+                    let status = response.status();
+                    let html = response
+                        .headers()
+                        .get(CONTENT_TYPE)
+                        .and_then(|v| v.to_str().ok())
+                        .is_some_and(|v| v.starts_with("text/html"));
+                    let content = response.bytes().await?;
+                    Ok::<_, ComposerError>((index, path, status, html, content))
+                });
+            }
+
+            let (index, path, status, html, content) = match tasks.join_next().await {
+                Some(res) => res??,
+                None => break,
+            };
+            let item = items[index];
+            let name = item.name.as_ref().expect("not found name");
+            let label = format!("{}({})", name, item.version);
+
+            let checked = if !status.is_success() {
+                Err(format!("the server answered {}", status))
+            } else if html {
+                Err("the server answered an HTML page".to_owned())
+            } else {
+                verify(&content, item)
+            };
+            if let Err(reason) = checked {
+                if stderr.is_tty() {
+                    stderr.write("");
+                }
+                stderr.write(&format!("  - Failed to download {}: {}", label, reason));
+                return Err(ComposerError::InvalidDist(label));
+            }
+
+            if let Some(dir) = path.parent() {
+                create_dir_all(dir)?;
+            }
+            let tmp = path.with_extension("part");
+            let mut f = File::create(&tmp)?;
+            f.write_all(&content)?;
+            rename(tmp, path)?;
+            hashes.insert(name.to_owned(), integrity(&content));
+
+            done += 1;
+            if stderr.is_tty() {
+                stderr.overwrite(&format!("  - Downloading ({}/{})", done, total));
+            } else {
+                stderr.write(&format!("  - Downloading {}", label));
+            }
+        }
+
+        if total > 0 && stderr.is_tty() {
+            stderr.write("");
+        }
+
+        Ok(hashes)
+    }
+}
+
+/// the url and commit of the git source of `item`
+pub(crate) fn git_source(item: &Version) -> Option<(&str, &str)> {
+    item.source
+        .as_ref()
+        .filter(|s| s._type == "git" && !s.reference.is_empty())
+        .map(|s| (s.url.as_str(), s.reference.as_str()))
+}
+
+/// whether `item` is installed as a checkout of its git source
+pub(crate) fn is_checkout(item: &Version, prefer_source: bool) -> bool {
+    prefer_source && git_source(item).is_some()
+}
+
+/// move the archive cached by version to `path`, only for tagged versions, as
+/// the archive of a branch may be of an older commit
+fn migrate(files_dir: &Path, item: &Version, path: &Path) -> Result<(), ComposerError> {
+    let legacy = legacy_dist_path(files_dir, item);
+    if !legacy.exists() {
+        return Ok(());
+    }
+    if !path.exists() && Stability::of(&item.version) != Stability::Dev {
+        rename(legacy, path)?;
+    } else {
+        remove_file(legacy)?;
+    }
+
+    Ok(())
+}
+
+/// check that `content` is the archive described by the metadata of `item`
+fn verify(content: &[u8], item: &Version) -> Result<(), String> {
+    use sha1::{Digest, Sha1};
+
+    if content.trim_ascii_start().starts_with(b"<") {
+        return Err("the server answered an HTML page".to_owned());
+    }
+
+    let shasum = item.dist.as_ref().and_then(|d| d.shasum.as_deref());
+    if let Some(shasum) = shasum.filter(|s| !s.is_empty()) {
+        let actual = hex::encode(Sha1::digest(content));
+        if !actual.eq_ignore_ascii_case(shasum) {
+            return Err(format!("the shasum {} does not match {}", actual, shasum));
+        }
+    }
+
+    zip::ZipArchive::new(Cursor::new(content))
+        .map(|_| ())
+        .map_err(|e| format!("not a valid zip archive, {}", e))
+}
+
+/// `sha256:<hex>` of the archive, recorded in composer.lock
+pub(crate) fn integrity(content: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+
+    format!("sha256:{}", hex::encode(Sha256::digest(content)))
+}
+
+/// `files/<name>/<reference>-<repository>.zip`, the repository is the origin of
+/// the dist url, so that the same reference from two repositories does not collide,
+/// or the url and commit of the git source without dist; a local archive is not cached
+pub(crate) fn dist_path(files_dir: &Path, item: &Version) -> PathBuf {
+    use sha1::{Digest, Sha1};
+
+    let name = item.name.as_ref().expect("not found name");
+    if let Some(dist) = item.dist.as_ref().filter(|d| d.local) {
+        return PathBuf::from(dist.url.trim_start_matches("file://"));
+    }
+    let (url, reference) = match (&item.dist, git_source(item)) {
+        (Some(dist), _) => (origin(&dist.url), dist.reference.as_str()),
+        (None, Some(source)) => source,
+        (None, None) => panic!("not found dist field"),
+    };
+
+    let reference: String = match &item.dist {
+        Some(dist) if reference.is_empty() => hex::encode(Sha1::digest(dist.url.as_bytes())),
+        _ => reference
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '_' | '-' => c,
+                _ => '-',
+            })
+            .collect(),
+    };
+    let repository = hex::encode(Sha1::digest(url.as_bytes()));
+
+    files_dir
+        .join(name)
+        .join(format!("{}-{}.zip", reference, &repository[..8]))
+}
+
+/// `files/<name>/<sha1 of version>.zip`, where archives were cached before
+fn legacy_dist_path(files_dir: &Path, item: &Version) -> PathBuf {
+    use sha1::{Digest, Sha1};
+
+    let name = item.name.as_ref().expect("not found name");
+    let file_name = format!("{}.zip", hex::encode(Sha1::digest(item.version.as_bytes())));
+
+    files_dir.join(name).join(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::{Method::GET, MockServer};
+    use serde_json::json;
+    use zip::write::SimpleFileOptions;
+
+    use crate::{http::tests::ParallelServer, io::tests::TestWriter};
+
+    use super::*;
+
+    fn archive() -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        // a fixed time, so that every archive has the same hash
+        let options = SimpleFileOptions::default().last_modified_time(zip::DateTime::default());
+        zip.start_file("bar/src/Bar.php", options).unwrap();
+        zip.write_all(b"<?php\n").unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn versions(url: impl Fn(&str) -> String, count: usize, shasum: &str) -> Vec<Version> {
+        (0..count)
+            .map(|i| {
+                serde_json::from_value(json!({
+                    "name": format!("foo/bar{i}"),
+                    "version": "1.0.0",
+                    "dist": {
+                        "url": url(&format!("/bar{i}.zip")),
+                        "type": "zip",
+                        "reference": "",
+                        "shasum": shasum,
+                    },
+                }))
+                .unwrap()
+            })
+            .collect()
+    }
+
+    /// download 4 archives, the most requests at once and the output
+    async fn download(max_parallel: usize) -> (usize, String) {
+        let server = ParallelServer::start(max_parallel, |_| Some(archive()));
+        let hits = |server: &ParallelServer| {
+            (0..4)
+                .map(|i| server.hits(&format!("/bar{i}.zip")))
+                .sum::<usize>()
+        };
+
+        let files_dir = tempfile::tempdir().unwrap();
+        let list = versions(|path| server.url(path), 4, "");
+        let mut stderr = TestWriter::new();
+
+        let hashes = Downloader::new(Http::insecure(), max_parallel)
+            .download(list.iter(), files_dir.path(), &mut stderr)
+            .await
+            .unwrap();
+
+        assert_eq!(hits(&server), 4);
+        for item in list.iter() {
+            assert!(dist_path(files_dir.path(), item).exists());
+        }
+        assert_eq!(hashes["foo/bar0"], integrity(&archive()));
+
+        // cached archives are not downloaded again
+        Downloader::new(Http::insecure(), max_parallel)
+            .download(list.iter(), files_dir.path(), &mut stderr)
+            .await
+            .unwrap();
+        assert_eq!(hits(&server), 4);
+
+        (server.peak(), stderr.output())
+    }
+
+    #[tokio::test]
+    async fn test_parallel() {
+        let (peak, output) = download(4).await;
+        assert_eq!(peak, 4);
+        for i in 0..4 {
+            assert!(output.contains(&format!("  - Downloading foo/bar{i}(1.0.0)")));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_parallel() {
+        let (peak, _) = download(2).await;
+        assert_eq!(peak, 2);
+    }
+
+    /// the download of one archive served by `then`, fails with the output
+    async fn rejected(shasum: &str, then: impl FnOnce(httpmock::Then)) -> String {
+        let server = MockServer::start();
+        server.mock(|when, t| {
+            when.method(GET);
+            then(t);
+        });
+
+        let files_dir = tempfile::tempdir().unwrap();
+        let list = versions(|path| server.url(path), 1, shasum);
+        let mut stderr = TestWriter::new();
+
+        let res = Downloader::new(Http::insecure(), 1)
+            .download(list.iter(), files_dir.path(), &mut stderr)
+            .await;
+        assert!(matches!(res, Err(ComposerError::InvalidDist(_))));
+        assert!(!dist_path(files_dir.path(), &list[0]).exists());
+
+        stderr.output()
+    }
+
+    #[tokio::test]
+    async fn test_reject() {
+        let output = rejected("", |then| {
+            then.status(404).body(archive());
+        })
+        .await;
+        assert_eq!(
+            output,
+            "  - Failed to download foo/bar0(1.0.0): the server answered 404 Not Found"
+        );
+
+        let output = rejected("", |then| {
+            then.status(200)
+                .header("content-type", "text/html; charset=utf-8")
+                .body("<html>login</html>");
+        })
+        .await;
+        assert!(output.ends_with("the server answered an HTML page"));
+
+        let output = rejected("", |then| {
+            then.status(200).body("not a zip");
+        })
+        .await;
+        assert!(output.contains("not a valid zip archive"));
+
+        let output = rejected("da39a3ee5e6b4b0d3255bfef95601890afd80709", |then| {
+            then.status(200).body(archive());
+        })
+        .await;
+        assert!(output.contains("does not match da39a3ee5e6b4b0d3255bfef95601890afd80709"));
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_cache() {
+        use sha1::{Digest, Sha1};
+
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET);
+            then.status(200).body(archive());
+        });
+
+        let files_dir = tempfile::tempdir().unwrap();
+        let list = versions(
+            |path| server.url(path),
+            1,
+            &hex::encode(Sha1::digest(archive())),
+        );
+        let path = dist_path(files_dir.path(), &list[0]);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(&path).unwrap().write_all(b"broken").unwrap();
+
+        let mut stderr = TestWriter::new();
+        Downloader::new(Http::insecure(), 1)
+            .download(list.iter(), files_dir.path(), &mut stderr)
+            .await
+            .unwrap();
+
+        mock.assert_hits(1);
+        assert_eq!(read(path).unwrap(), archive());
+    }
+
+    fn version(url: &str, version: &str, reference: &str) -> Version {
+        serde_json::from_value(json!({
+            "name": "foo/bar",
+            "version": version,
+            "dist": { "url": url, "type": "zip", "reference": reference },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_dist_path() {
+        let dir = Path::new("files");
+        let a = version("https://a.org/bar/zipball/abc", "1.0.0", "abc");
+        let path = dist_path(dir, &a);
+        assert!(path.starts_with("files/foo/bar"));
+        assert!(path.to_str().unwrap().contains("/abc-"));
+
+        // a new commit of the same version
+        let b = version("https://a.org/bar/zipball/def", "1.0.0", "def");
+        assert_ne!(dist_path(dir, &b), path);
+        // the same reference from another repository
+        let c = version("https://b.org/bar/zipball/abc", "1.0.0", "abc");
+        assert_ne!(dist_path(dir, &c), path);
+        // the same repository
+        let d = version("https://a.org/other/abc.zip", "1.0.1", "abc");
+        assert_eq!(dist_path(dir, &d), path);
+
+        let e = version("https://a.org/bar.zip", "1.0.0", "../x");
+        assert!(dist_path(dir, &e).to_str().unwrap().contains("/..-x-"));
+    }
+
+    #[tokio::test]
+    async fn test_migrate() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET);
+            then.status(200).body(archive());
+        });
+        let url = server.url("/bar.zip");
+        let files_dir = tempfile::tempdir().unwrap();
+
+        for (pretty, hits) in [("1.0.0", 0), ("dev-main", 1)] {
+            let item = version(&url, pretty, "abc");
+            let legacy = legacy_dist_path(files_dir.path(), &item);
+            create_dir_all(legacy.parent().unwrap()).unwrap();
+            File::create(&legacy)
+                .unwrap()
+                .write_all(&archive())
+                .unwrap();
+
+            let mut stderr = TestWriter::new();
+            Downloader::new(Http::insecure(), 1)
+                .download([item.clone()].iter(), files_dir.path(), &mut stderr)
+                .await
+                .unwrap();
+
+            mock.assert_hits(hits);
+            assert!(!legacy.exists());
+            assert!(dist_path(files_dir.path(), &item).exists());
+            remove_file(dist_path(files_dir.path(), &item)).unwrap();
+        }
+    }
+}
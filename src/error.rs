@@ -1,32 +1,100 @@
-use std::fmt::{self, Display};
-
 use thiserror::Error;
 use zip::result::ZipError;
 
 #[derive(Debug, Error)]
 pub enum ComposerError {
+    #[error("http request failed: {0}")]
     Reqwest(#[from] reqwest::Error),
 
+    #[error("{0}")]
     Io(#[from] std::io::Error),
 
+    #[error("invalid json: {0}")]
     Json(#[from] serde_json::Error),
 
     #[allow(dead_code)]
+    #[error("package {0} not found")]
     NotFoundPackage(String),
 
+    #[error("the home directory was not found")]
     NotFoundHomeDir,
 
+    #[error("invalid zip archive: {0}")]
     Zip(#[from] ZipError),
 
-    Semver(#[from] semver::Error),
+    #[error("invalid version {0}")]
+    InvalidVersion(String),
+
+    #[error("invalid constraint {0}")]
+    InvalidConstraint(String),
 
+    #[error("invalid stability {0}")]
+    InvalidStability(String),
+
+    #[error("the php version could not be read")]
     GetPhpVersionFailed,
 
-    PhpVersion,
+    #[error("your requirements could not be resolved")]
+    Unresolvable,
+
+    #[error("composer.lock is not up to date with composer.json, run update")]
+    StaleLock,
+
+    #[error("invalid json: {0}")]
+    InvalidJson(String),
+
+    #[error("the archive of {0} could not be installed")]
+    InvalidDist(String),
+
+    #[error("the archive of {0} does not match the integrity of composer.lock")]
+    IntegrityMismatch(String),
+
+    #[error("git failed: {0}")]
+    Git(String),
+
+    #[error("refused the insecure url {0}, secure-http is enabled")]
+    InsecureUrl(String),
+
+    /// the credentials for the url are missing or refused
+    #[error("the credentials for {0} are missing or refused, see auth.json")]
+    Unauthorized(String),
+
+    /// a status other than not found, for the url
+    #[error("status {0} for {1}")]
+    HttpStatus(u16, String),
+
+    /// a task which panicked or was cancelled
+    #[error("a task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
 }
 
-impl Display for ComposerError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        "composer2 error".fmt(f)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            ComposerError::HttpStatus(500, "https://repo.example.com/p2/a/b.json".to_owned())
+                .to_string(),
+            "status 500 for https://repo.example.com/p2/a/b.json"
+        );
+        assert_eq!(
+            ComposerError::Git("git clone: fatal: repository not found".to_owned()).to_string(),
+            "git failed: git clone: fatal: repository not found"
+        );
+        assert!(
+            ComposerError::Unauthorized("https://repo.example.com".to_owned())
+                .to_string()
+                .contains("https://repo.example.com")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_task() {
+        let result: Result<(), ComposerError> = tokio::spawn(async { panic!("broken") })
+            .await
+            .map_err(Into::into);
+        assert!(matches!(result, Err(ComposerError::Task(_))));
     }
 }
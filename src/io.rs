@@ -1,5 +1,17 @@
+use std::io::IsTerminal;
+
 pub trait ErrWriter {
     fn write(&mut self, s: &str);
+
+    /// can a line be updated in place
+    fn is_tty(&self) -> bool {
+        false
+    }
+
+    /// replace the current line with `s`, without ending it
+    fn overwrite(&mut self, s: &str) {
+        self.write(s);
+    }
 }
 
 pub struct StderrWriter;
@@ -8,6 +20,14 @@ impl ErrWriter for StderrWriter {
     fn write(&mut self, s: &str) {
         eprintln!("{}", s);
     }
+
+    fn is_tty(&self) -> bool {
+        std::io::stderr().is_terminal()
+    }
+
+    fn overwrite(&mut self, s: &str) {
+        eprint!("\r\x1b[2K{}", s);
+    }
 }
 
 #[cfg(test)]
@@ -1,38 +1,88 @@
 //! parse composer.json
 
 use std::{
+    collections::HashSet,
     fs::{read_to_string, remove_dir_all, File},
     io::Write,
-    path::Path,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use indexmap::IndexMap;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 use crate::{
+    auth::Auth,
     autoload::{FilesData, Psr4Data, StaticData},
-    config::{GlobalConfig, Packagist, Repositories},
+    config::{GlobalConfig, Repositories, RepositoryConfig, PACKAGIST},
+    constraint::{inline_alias, normalize, Stability},
+    download::{Downloader, MAX_PARALLEL_HTTP},
     error::ComposerError,
+    http::{Http, HttpOptions},
     io::ErrWriter,
-    package::{AutoloadEnum, ComposerLock, Context, P2},
+    package::{
+        cache_dir, is_platform, Alias, AutoloadEnum, ComposerLock, Context, Require, Version, P2,
+    },
+    repository::{artifact::mark_local, RepositorySet},
+    resolver::{Requirement, Resolver},
 };
 
+mod manipulator;
 #[cfg(test)]
 mod tests;
 
-const PACKAGE_URL: &str = "https://repo.packagist.org/";
+use manipulator::JsonManipulator;
+
+const PACKAGE_URL: &str = "https://repo.packagist.org";
+/// the keys of composer.json which are part of the content-hash
+const RELEVANT_KEYS: [&str; 11] = [
+    "name",
+    "version",
+    "require",
+    "require-dev",
+    "conflict",
+    "replace",
+    "provide",
+    "minimum-stability",
+    "prefer-stable",
+    "repositories",
+    "extra",
+];
+const EMPTY_JSON: &str = "{\n    \"require\": {}\n}\n";
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub(crate) struct Composer {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) require: Option<IndexMap<String, String>>,
 
+    #[serde(rename = "require-dev")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) require_dev: Option<IndexMap<String, String>>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     repositories: Option<Repositories>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) autoload: Option<AutoloadEnum>,
+
+    #[serde(rename = "autoload-dev")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) autoload_dev: Option<AutoloadEnum>,
+
+    #[serde(rename = "minimum-stability")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) minimum_stability: Option<String>,
+
+    #[serde(rename = "prefer-stable")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) prefer_stable: Option<bool>,
+
+    /// the text of composer.json, where the changes are written into
+    #[serde(skip)]
+    contents: String,
 }
 
 impl Composer {
@@ -43,13 +93,18 @@ impl Composer {
             Ok(c) => c,
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
                 let mut file = File::create(path)?;
-                file.write_all(b"{\"require\":{}}")?;
-                String::from("{\"require\":{}}")
+                file.write_all(EMPTY_JSON.as_bytes())?;
+                String::from(EMPTY_JSON)
             }
             Err(e) => return Err(ComposerError::Io(e)),
         };
 
-        let cp: Self = serde_json::from_str(&content)?;
+        Self::from_str(&content)
+    }
+
+    pub(crate) fn from_str(content: &str) -> Result<Composer, ComposerError> {
+        let mut cp: Self = serde_json::from_str(content)?;
+        cp.contents = content.to_owned();
 
         Ok(cp)
     }
@@ -59,112 +114,141 @@ impl Composer {
         stderr: &mut dyn ErrWriter,
         ctx: Arc<Mutex<Context>>,
     ) -> Result<ComposerLock, ComposerError> {
-        if let Some(ref list) = self.require {
-            for (name, version) in list.iter() {
-                {
-                    let mut c = ctx.lock().unwrap();
-                    c.first_package = None;
-                }
-
-                let origin_version = version.clone();
+        {
+            let mut c = ctx.lock().unwrap();
+            c.minimum_stability = self.minimum_stability()?;
+            c.prefer_stable = self.prefer_stable.unwrap_or(false);
+            c.stability_flags = self.stability_flags(c.minimum_stability);
+            c.aliases = self.aliases()?;
+        }
 
-                let version = if version == "*" {
-                    None
-                } else {
-                    Some(version.to_owned())
-                };
+        let mut requirements = Vec::new();
+        let mut packages = Vec::new();
+        for (name, version) in self.requires(true) {
+            requirements.push(Requirement {
+                by: None,
+                name: name.to_owned(),
+                constraint: version.to_owned(),
+            });
+            if is_platform(name) {
+                continue;
+            }
 
-                P2::down_all(name.to_owned(), version, ctx.clone())
-                    .await
-                    .expect("download error");
+            let version = if version == "*" {
+                None
+            } else {
+                Some(version.to_owned())
+            };
+            packages.push((name.to_owned(), version));
+        }
 
-                let c = ctx.lock().unwrap();
-                if let Some(p) = &c.first_package {
-                    let version = &p.version;
-                    let mut this = Self::new()?;
-                    this.set_version(name, version);
-                    this.save()?;
-                }
+        P2::down_all(packages, ctx.clone()).await?;
 
-                Self::eprint_php_version(name, &origin_version, &c.php_version_error, stderr)?;
-                Self::eprint_extensions(name, &origin_version, &c.php_extensions_error, stderr)?;
+        let resolved = {
+            let c = ctx.lock().unwrap();
+            Resolver::new(&c).resolve(requirements)
+        };
+        let versions = match resolved {
+            Ok(versions) => versions,
+            Err(conflict) => {
+                stderr.write(&conflict.explain().join("\n"));
+                return Err(ComposerError::Unresolvable);
             }
-        }
+        };
 
-        Ok(ComposerLock::new(ctx))
-    }
+        for item in versions.iter() {
+            stderr.write(&format!(
+                "  - Locking {}({})",
+                item.name.as_deref().unwrap_or_default(),
+                item.version
+            ));
+        }
 
-    /// php version is not satisfy, return failure
-    fn eprint_php_version(
-        name: &str,
-        origin_version: &str,
-        list: &[(String, String)],
-        stderr: &mut dyn ErrWriter,
-    ) -> Result<(), ComposerError> {
-        if !list.is_empty() {
-            for (i, item) in list.iter().enumerate() {
-                stderr.write(&format!(
-                    "{name}({}) -> .. -> {} need PHP version is {}",
-                    origin_version, item.0, item.1
-                ));
-                if i > 2 {
-                    break;
+        if self.requires(true).any(|(_, v)| v == "*") {
+            let mut this = Self::new()?;
+            for item in versions.iter() {
+                if let Some(name) = &item.name {
+                    this.set_version(name, &item.version);
                 }
             }
-
-            // rollback
-            let mut this = Self::new()?;
-            this.only_remove(name);
             this.save()?;
-
-            return Err(ComposerError::PhpVersion);
         }
 
-        Ok(())
+        let (versions, versions_dev) = self.split_dev(versions);
+        let mut c = ctx.lock().unwrap();
+        c.versions = versions;
+        c.versions_dev = versions_dev;
+        drop(c);
+
+        let mut lock = ComposerLock::new(ctx);
+        lock.content_hash = self.content_hash()?;
+        lock.platform = self.platform(false);
+        lock.platform_dev = self.platform(true);
+
+        Ok(lock)
     }
 
-    fn eprint_extensions(
-        name: &str,
-        origin_version: &str,
-        list: &[(String, String)],
-        stderr: &mut dyn ErrWriter,
-    ) -> Result<(), ComposerError> {
-        if !list.is_empty() {
-            for (i, item) in list.iter().enumerate() {
-                stderr.write(&format!(
-                    "{name}({}) -> .. -> {} need ext-{},it is missing from your system. Install or enable PHP's {} extension.",
-                    origin_version, item.0, item.1,item.1
-                ));
-                if i > 2 {
-                    break;
-                }
-            }
+    /// the root requirements, those of require-dev too with `dev`
+    pub(crate) fn requires(&self, dev: bool) -> impl Iterator<Item = (&String, &String)> {
+        let require_dev = self.require_dev.iter().filter(move |_| dev);
+        self.require.iter().chain(require_dev).flatten()
+    }
 
-            // rollback
-            let mut this = Self::new()?;
-            this.only_remove(name);
-            this.save()?;
+    /// the autoload of composer.json, and its autoload-dev with `dev`
+    pub(crate) fn autoloads(&self, dev: bool) -> impl Iterator<Item = &AutoloadEnum> {
+        let autoload_dev = self.autoload_dev.iter().filter(move |_| dev);
+        self.autoload.iter().chain(autoload_dev)
+    }
 
-            return Err(ComposerError::PhpVersion);
+    /// split the resolved versions into those needed by require, and those
+    /// only needed by require-dev
+    fn split_dev(&self, versions: Vec<Version>) -> (Vec<Version>, Vec<Version>) {
+        let mut needed = HashSet::new();
+        let mut pending: Vec<String> = self.requires(false).map(|(n, _)| n.to_owned()).collect();
+        while let Some(name) = pending.pop() {
+            if !needed.insert(name.clone()) {
+                continue;
+            }
+            let item = versions.iter().find(|v| v.name.as_ref() == Some(&name));
+            if let Some(Require::Map(deps)) = item.and_then(|v| v.require.as_ref()) {
+                pending.extend(deps.keys().filter(|n| !is_platform(n)).cloned());
+            }
         }
 
-        Ok(())
+        versions
+            .into_iter()
+            .partition(|v| needed.contains(v.name.as_deref().unwrap_or_default()))
     }
 
     pub async fn install(
         &mut self,
         name: &str,
+        dev: bool,
         stderr: &mut dyn ErrWriter,
     ) -> Result<(), ComposerError> {
-        let p2_url = self.get_package_url()?;
+        let repositories = self.repositories()?;
         let mut context = Context::new()?;
 
-        context.p2_url = p2_url;
+        context.repositories = repositories;
+        context.metadata_ttl = Some(self.metadata_ttl()?);
+        context.http = self.http()?;
+        let downloader = self.downloader(context.http.clone())?;
 
         let ctx = Arc::new(Mutex::new(context));
-        let packages = self.get_lock(stderr, ctx).await?;
+        let mut packages = match self.get_lock(stderr, ctx).await {
+            Ok(packages) => packages,
+            Err(e) => {
+                // rollback the package which is just required
+                if !name.is_empty() {
+                    self.only_remove(name);
+                    self.save()?;
+                }
+                return Err(e);
+            }
+        };
 
-        packages.installing().await?;
+        packages.installing(dev, &downloader, stderr).await?;
+        self.dump_autoload(dev)?;
 
         if !name.is_empty() {
             if let Some(version) = packages.find_version(name) {
@@ -176,22 +260,244 @@ impl Composer {
         Ok(())
     }
 
+    /// install the versions recorded in composer.lock, resolve composer.json
+    /// only when there is no lock file yet
+    pub async fn install_from_lock(
+        &mut self,
+        dev: bool,
+        stderr: &mut dyn ErrWriter,
+    ) -> Result<(), ComposerError> {
+        if !Path::new("./composer.lock").exists() {
+            return self.install("", dev, stderr).await;
+        }
+
+        let lock = self.read_lock()?;
+        let problems = self.lock_problems(&lock);
+        // locks written before phpp had a content-hash are only checked by their packages
+        let changed = !lock.content_hash.is_empty() && lock.content_hash != self.content_hash()?;
+        if changed || !problems.is_empty() {
+            let mut lines = vec![
+                "The lock file is not up to date with the latest changes in composer.json, run `phpp update` to update it."
+                    .to_owned(),
+            ];
+            lines.extend(problems.iter().map(|p| format!("  - {}", p)));
+            stderr.write(&lines.join("\n"));
+            return Err(ComposerError::StaleLock);
+        }
+
+        stderr.write("Installing dependencies from lock file");
+        let downloader = self.downloader(self.http()?)?;
+        lock.install(dev, &downloader, stderr).await?;
+        self.dump_autoload(dev)
+    }
+
+    /// composer.lock, with the archives of the artifact repositories of
+    /// composer.json read from the disk
+    fn read_lock(&self) -> Result<ComposerLock, ComposerError> {
+        let mut lock = ComposerLock::from_file()?;
+        let dirs: Vec<PathBuf> = self
+            .repositories()?
+            .iter()
+            .filter(|config| config._type == "artifact")
+            .map(|config| PathBuf::from(&config.url))
+            .collect();
+        mark_local(
+            lock.packages.iter_mut().chain(lock.packages_dev.iter_mut()),
+            &dirs,
+        );
+        Ok(lock)
+    }
+
+    /// downloads at most `config.max-parallel-http` archives at the same time,
+    /// `COMPOSER_MAX_PARALLEL_HTTP` takes precedence
+    fn downloader(&self, http: Http) -> Result<Downloader, ComposerError> {
+        let from_env = std::env::var("COMPOSER_MAX_PARALLEL_HTTP")
+            .ok()
+            .and_then(|v| v.parse().ok());
+        let max_parallel = match from_env {
+            Some(n) => n,
+            None => self
+                .config("max-parallel-http")?
+                .and_then(|v| v.as_u64())
+                .map_or(MAX_PARALLEL_HTTP, |n| n as usize),
+        };
+
+        let prefer_source = self
+            .config("preferred-install")?
+            .is_some_and(|v| v.as_str() == Some("source"));
+
+        Ok(Downloader::new(http, max_parallel).prefer_source(prefer_source))
+    }
+
+    /// the client of every request, with `config.secure-http`, `config.cafile`,
+    /// `config.capath`, `config.http-timeout` seconds and the credentials of auth.json
+    pub(crate) fn http(&self) -> Result<Http, ComposerError> {
+        let content: Value = serde_json::from_str(&self.to_json()?)?;
+        let config = match content.get("config") {
+            Some(Value::Object(config)) => config.clone(),
+            _ => Map::new(),
+        };
+        let options = HttpOptions::from_config(&config, Path::new(""));
+
+        Ok(Http::new(&options)?.auth(Arc::new(Auth::load()?)))
+    }
+
+    /// cached metadata is revalidated once older than `config.metadata-ttl`
+    /// seconds, every time by default
+    fn metadata_ttl(&self) -> Result<Duration, ComposerError> {
+        let ttl = self
+            .config("metadata-ttl")?
+            .and_then(|v| v.as_u64())
+            .unwrap_or_default();
+
+        Ok(Duration::from_secs(ttl))
+    }
+
+    /// the value of `key` in the config section
+    fn config(&self, key: &str) -> Result<Option<Value>, ComposerError> {
+        let mut content: Value = serde_json::from_str(&self.to_json()?)?;
+
+        Ok(content
+            .get_mut("config")
+            .and_then(|config| config.get_mut(key))
+            .map(Value::take))
+    }
+
+    /// the md5 of the keys of composer.json which affect the lock file, the same
+    /// hash composer writes as `content-hash`
+    pub(crate) fn content_hash(&self) -> Result<String, ComposerError> {
+        use md5::{Digest, Md5};
+
+        let content: Value = serde_json::from_str(&self.to_json()?)?;
+
+        let mut relevant: Vec<(&str, Value)> = Vec::new();
+        for key in RELEVANT_KEYS {
+            if let Some(value) = content.get(key) {
+                relevant.push((key, value.clone()));
+            }
+        }
+        if let Some(platform) = content.pointer("/config/platform") {
+            let mut config = serde_json::Map::new();
+            config.insert("platform".to_owned(), platform.clone());
+            relevant.push(("config", Value::Object(config)));
+        }
+        relevant.sort_by(|a, b| a.0.cmp(b.0));
+
+        let relevant = relevant
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value))
+            .collect();
+        let mut json = String::new();
+        php_json(&Value::Object(relevant), &mut json);
+
+        let mut hasher = Md5::new();
+        hasher.update(json.as_bytes());
+
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    /// the platform requirements of require, or of require-dev with `dev`
+    fn platform(&self, dev: bool) -> IndexMap<String, String> {
+        let list = if dev {
+            &self.require_dev
+        } else {
+            &self.require
+        };
+        list.iter()
+            .flatten()
+            .filter(|(name, _)| is_platform(name))
+            .map(|(name, constraint)| (name.to_owned(), constraint.to_owned()))
+            .collect()
+    }
+
+    /// the root requirements which the lock file does not satisfy
+    fn lock_problems(&self, lock: &ComposerLock) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (name, constraint) in self.requires(true) {
+            if is_platform(name) {
+                continue;
+            }
+            match lock.find_version(name) {
+                None => problems.push(format!(
+                    "Required package \"{}\" is not present in the lock file.",
+                    name
+                )),
+                Some(item) if !lock.satisfies(item, constraint) => problems.push(format!(
+                    "Required package \"{}\" is in the lock file as \"{}\" but that does not satisfy your constraint \"{}\".",
+                    name, item.version, constraint
+                )),
+                _ => {}
+            }
+        }
+        problems
+    }
+
+    fn minimum_stability(&self) -> Result<Stability, ComposerError> {
+        match &self.minimum_stability {
+            Some(name) => Stability::parse(name),
+            None => Ok(Stability::Stable),
+        }
+    }
+
+    /// the stability flags of the root requirements, like `foo/bar: ^2.0@beta`
+    fn stability_flags(&self, minimum: Stability) -> IndexMap<String, Stability> {
+        let mut flags = IndexMap::new();
+        for (name, version) in self.requires(true) {
+            if let Some(stability) = Stability::flag(version, minimum) {
+                flags.insert(name.to_lowercase(), stability);
+            }
+        }
+        flags
+    }
+
+    /// the inline aliases of the root requirements, like `foo/bar: dev-main as 1.5.0`
+    fn aliases(&self) -> Result<Vec<Alias>, ComposerError> {
+        let mut aliases = Vec::new();
+        for (name, version) in self.requires(true) {
+            if let Some((version, alias)) = inline_alias(version) {
+                aliases.push(Alias {
+                    package: name.to_lowercase(),
+                    version: normalize(version)?,
+                    alias: alias.to_owned(),
+                    alias_normalized: normalize(alias)?,
+                });
+            }
+        }
+        Ok(aliases)
+    }
+
     fn set_version(&mut self, name: &str, version: &str) {
-        if let Some(mut list) = self.require.take() {
+        for list in [&mut self.require, &mut self.require_dev]
+            .into_iter()
+            .flatten()
+        {
             list.entry(name.to_string()).and_modify(|e| {
                 if e == "*" {
                     *e = version.to_string();
                 }
             });
-            self.require = Some(list);
         }
     }
 
-    pub fn insert(&mut self, name: &str, version: &Option<String>) -> Result<(), ComposerError> {
+    /// add a requirement to require, or to require-dev with `dev`
+    pub fn insert(
+        &mut self,
+        name: &str,
+        version: &Option<String>,
+        dev: bool,
+    ) -> Result<(), ComposerError> {
         let star = String::from("*");
         let version = version.as_ref().unwrap_or(&star);
 
-        self.require = match self.require.take() {
+        // a package is required by only one of require and require-dev
+        self.only_remove(name);
+
+        let require = if dev {
+            &mut self.require_dev
+        } else {
+            &mut self.require
+        };
+        *require = match require.take() {
             Some(mut list) => {
                 list.insert(name.to_owned(), version.to_owned());
                 //list.sort_keys();
@@ -210,74 +516,241 @@ impl Composer {
     }
 
     fn only_remove(&mut self, name: &str) {
-        let require = self.require.take();
-        if let Some(mut list) = require {
-            list.swap_remove(name);
-            self.require = Some(list);
+        for list in [&mut self.require, &mut self.require_dev]
+            .into_iter()
+            .flatten()
+        {
+            list.shift_remove(name);
         }
     }
+
+    /// remove a requirement from require, or from require-dev with `dev`
     pub async fn remove(
         &mut self,
         name: &str,
+        dev: bool,
         stderr: &mut dyn ErrWriter,
     ) -> Result<(), ComposerError> {
-        let p2_url = self.get_package_url()?;
+        let repositories = self.repositories()?;
         let mut context = Context::new()?;
 
-        context.p2_url = p2_url;
+        context.repositories = repositories;
+        context.metadata_ttl = Some(self.metadata_ttl()?);
+        context.http = self.http()?;
 
         let ctx = Arc::new(Mutex::new(context));
 
-        let require = self.require.take();
-        if let Some(mut list) = require {
-            list.swap_remove(name);
-            self.require = Some(list);
+        let (section, other, other_list) = if dev {
+            ("require-dev", "require", &self.require)
+        } else {
+            ("require", "require-dev", &self.require_dev)
+        };
+        if other_list.as_ref().is_some_and(|l| l.contains_key(name)) {
+            stderr.write(&format!(
+                "{} could not be found in {} but it is present in {}",
+                name, section, other
+            ));
         }
+        self.only_remove(name);
 
         let new_lock = self.get_lock(stderr, ctx).await?;
-        let old_lock = ComposerLock::from_file()?;
+        let old_lock = self.read_lock()?;
         let deleteing = old_lock.get_deleteing_packages(&new_lock)?;
 
-        let vendor = Path::new("./vendor");
-        for item in deleteing.iter() {
-            remove_dir_all(vendor.join(item))?;
-        }
-        for item in deleteing.iter() {
-            let path = vendor.join(item);
-            if let Some(parent) = path.parent() {
-                if let Ok(res) = has_files(parent) {
-                    if !res {
-                        remove_dir_all(parent)?;
+        remove_vendor_dirs(&deleteing)?;
+
+        new_lock.update_autoload_files(true)?;
+
+        Ok(())
+    }
+
+    /// re-resolve composer.json, keeping every locked package which is not
+    /// allowed to update at its locked version
+    pub async fn update(
+        &mut self,
+        packages: &[String],
+        with: UpdateWith,
+        lock_only: bool,
+        dev: bool,
+        stderr: &mut dyn ErrWriter,
+    ) -> Result<(), ComposerError> {
+        let repositories = self.repositories()?;
+        let mut context = Context::new()?;
+
+        context.repositories = repositories;
+        context.metadata_ttl = Some(self.metadata_ttl()?);
+        context.http = self.http()?;
+        context.aliases = self.aliases()?;
+        let downloader = self.downloader(context.http.clone())?;
+
+        let old_lock = if Path::new("./composer.lock").exists() {
+            Some(self.read_lock()?)
+        } else {
+            None
+        };
+
+        if let Some(ref old_lock) = old_lock {
+            if lock_only || !packages.is_empty() {
+                let allowed = if lock_only {
+                    HashSet::new()
+                } else {
+                    self.update_allowlist(old_lock, packages, with, stderr)
+                };
+                for item in old_lock.installed(true) {
+                    if !allowed.contains(item.name.as_deref().unwrap_or_default()) {
+                        context.pin(item.clone());
                     }
                 }
             }
         }
 
-        fn has_files(path: &Path) -> Result<bool, std::io::Error> {
-            for entry in std::fs::read_dir(path)? {
-                let entry = entry?;
-                let file_type = entry.file_type()?;
-                if file_type.is_file() || file_type.is_dir() {
-                    return Ok(true);
+        let ctx = Arc::new(Mutex::new(context));
+        let mut new_lock = self.get_lock(stderr, ctx).await?;
+
+        if lock_only {
+            return new_lock.save_file();
+        }
+
+        if let Some(old_lock) = old_lock {
+            for item in old_lock.get_deleteing_packages(&new_lock)? {
+                stderr.write(&format!("  - Removing {}", item));
+                remove_vendor_dirs(&HashSet::from([item]))?;
+            }
+            // updated packages are extracted again into an empty directory
+            for item in old_lock.get_updating_packages(&new_lock) {
+                remove_vendor_dirs(&HashSet::from([item]))?;
+            }
+        }
+
+        new_lock.installing(dev, &downloader, stderr).await?;
+        self.dump_autoload(dev)
+    }
+
+    /// the locked packages named in `packages`, wildcards like `foo/*` included,
+    /// and their dependencies as asked by `with`
+    fn update_allowlist(
+        &self,
+        lock: &ComposerLock,
+        packages: &[String],
+        with: UpdateWith,
+        stderr: &mut dyn ErrWriter,
+    ) -> HashSet<String> {
+        let mut allowed = HashSet::new();
+        for pattern in packages.iter() {
+            let re = format!(
+                "^{}$",
+                regex::escape(&pattern.to_lowercase()).replace(r"\*", ".*")
+            );
+            let re = match Regex::new(&re) {
+                Ok(re) => re,
+                Err(_) => continue,
+            };
+            let mut found = false;
+            for item in lock.installed(true) {
+                let name = item.name.as_deref().unwrap_or_default();
+                if re.is_match(&name.to_lowercase()) {
+                    allowed.insert(name.to_owned());
+                    found = true;
                 }
             }
-            Ok(false)
+            if !found {
+                stderr.write(&format!(
+                    "Package \"{}\" listed for update is not locked.",
+                    pattern
+                ));
+            }
         }
 
-        new_lock.update_autoload_files()?;
+        if with == UpdateWith::Nothing {
+            return allowed;
+        }
 
-        Ok(())
+        let root: HashSet<&String> = self.requires(true).map(|(name, _)| name).collect();
+        let mut pending: Vec<String> = allowed.iter().cloned().collect();
+        while let Some(name) = pending.pop() {
+            let deps = match lock.find_version(&name).and_then(|v| v.require.as_ref()) {
+                Some(Require::Map(deps)) => deps,
+                _ => continue,
+            };
+            for dep_name in deps.keys() {
+                if is_platform(dep_name)
+                    || (with == UpdateWith::Dependencies && root.contains(dep_name))
+                {
+                    continue;
+                }
+                if lock.find_version(dep_name).is_some() && allowed.insert(dep_name.to_owned()) {
+                    pending.push(dep_name.to_owned());
+                }
+            }
+        }
+
+        allowed
     }
 
     pub fn save(&self) -> Result<(), ComposerError> {
         let path = Path::new("./composer.json");
+        let content = self.to_json()?;
         let mut f = File::create(path)?;
-        let content = serde_json::to_string_pretty(&self)?;
         f.write_all(content.as_bytes())?;
 
         Ok(())
     }
 
+    /// the text of composer.json with the changes of this struct, only the
+    /// changed keys are written
+    pub(crate) fn to_json(&self) -> Result<String, ComposerError> {
+        let contents = if self.contents.trim().is_empty() {
+            EMPTY_JSON
+        } else {
+            &self.contents
+        };
+        let mut json = JsonManipulator::new(contents)?;
+
+        let before = serde_json::to_value(Self::from_str(contents)?)?;
+        let after = serde_json::to_value(self)?;
+        let empty = serde_json::Map::new();
+        let before = before.as_object().unwrap_or(&empty);
+        let after = after.as_object().unwrap_or(&empty);
+
+        let sort_packages = serde_json::from_str::<serde_json::Value>(contents)?
+            .pointer("/config/sort-packages")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        let keys: Vec<&String> = before
+            .keys()
+            .chain(after.keys().filter(|k| !before.contains_key(*k)))
+            .collect();
+        for key in keys {
+            let (old, new) = (before.get(key), after.get(key));
+            if old == new {
+                continue;
+            }
+            match (old, new) {
+                (_, None) => {
+                    json.remove_main_key(key)?;
+                }
+                // links are changed one by one, to keep their order and formatting
+                (Some(Value::Object(old)), Some(Value::Object(new)))
+                    if key == "require" || key == "require-dev" =>
+                {
+                    for name in old.keys().filter(|name| !new.contains_key(*name)) {
+                        json.remove_sub_node(key, name)?;
+                    }
+                    for (name, constraint) in new.iter() {
+                        if old.get(name) != Some(constraint) {
+                            let constraint = constraint.as_str().unwrap_or_default();
+                            json.add_link(key, name, constraint, sort_packages)?;
+                        }
+                    }
+                }
+                (_, Some(value)) => json.add_main_key(key, value)?,
+            }
+        }
+
+        Ok(json.contents().to_owned())
+    }
+
     pub fn set(
         &mut self,
         unset: bool,
@@ -289,10 +762,12 @@ impl Composer {
             "repo.packagist" => {
                 if !unset {
                     if let (Some(value1), Some(value2)) = (value1, value2) {
-                        self.set_repo(value1, value2)?;
+                        self.repositories
+                            .get_or_insert_with(Repositories::default)
+                            .set(PACKAGIST, value1, value2);
                     }
-                } else {
-                    self.repositories = None;
+                } else if let Some(repositories) = &mut self.repositories {
+                    repositories.remove(PACKAGIST);
                 }
             }
             _ => todo!(),
@@ -301,53 +776,47 @@ impl Composer {
         Ok(())
     }
 
-    fn set_repo(&mut self, value1: &str, value2: &str) -> Result<(), ComposerError> {
-        let repo = self.repositories.take();
-
-        self.repositories = match repo {
-            Some(mut repo) => {
-                repo.packagist._type = value1.to_owned();
-                repo.packagist.url = value2.to_owned();
-                Some(repo)
-            }
-            None => {
-                let repo = Repositories {
-                    packagist: Packagist {
-                        _type: value1.to_owned(),
-                        url: value2.to_owned(),
-                    },
-                };
-                Some(repo)
-            }
-        };
-
-        Ok(())
+    /// the repositories of composer.json, for the commands which do not resolve
+    pub async fn repository(&self) -> Result<RepositorySet, ComposerError> {
+        RepositorySet::new(
+            &self.repositories()?,
+            self.http()?,
+            cache_dir()?,
+            Some(self.metadata_ttl()?),
+        )
+        .await
     }
 
-    pub fn get_package_url(&self) -> Result<String, ComposerError> {
-        // PACKAGE_URL
-        let mut url = String::from(PACKAGE_URL);
-        if let Some(repositories) = &self.repositories {
-            url = repositories.packagist.url.to_owned();
-        } else {
-            let config = GlobalConfig::new()?;
-            if let Some(repositories) = config.repositories {
-                url = repositories.packagist.url.to_owned();
-            }
+    /// the repositories of composer.json, then those of the global config, then
+    /// packagist unless it is disabled
+    pub fn repositories(&self) -> Result<Vec<RepositoryConfig>, ComposerError> {
+        let global = GlobalConfig::new()?.repositories.unwrap_or_default();
+        let local = self.repositories.clone().unwrap_or_default();
+
+        let mut list: Vec<RepositoryConfig> = local.configs().cloned().collect();
+        list.extend(global.configs().cloned());
+        if !local.packagist_disabled() && !global.packagist_disabled() {
+            let packagist = local
+                .packagist()
+                .or(global.packagist())
+                .cloned()
+                .unwrap_or_else(|| RepositoryConfig::new("composer", PACKAGE_URL));
+            list.push(packagist);
         }
 
-        url.push_str("/p2/");
-
-        Ok(url)
+        Ok(list)
     }
 
-    pub fn dump_autoload(&self) -> Result<(), ComposerError> {
+    /// write the autoload of composer.json, and its autoload-dev with `dev`
+    pub fn dump_autoload(&self, dev: bool) -> Result<(), ComposerError> {
         let mut psr4 = Psr4Data::new()?;
-        psr4.append_json(self);
+        psr4.keep(true);
+        psr4.append_json(self, dev);
         psr4.write()?;
 
         let mut files = FilesData::new()?;
-        files.append_json(self);
+        files.keep(true);
+        files.append_json(self, dev);
         files.write()?;
 
         let static_files = StaticData::from(&files, &psr4);
@@ -355,3 +824,106 @@ impl Composer {
         Ok(())
     }
 }
+
+/// which dependencies of the packages named in `update` are updated with them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UpdateWith {
+    Nothing,
+    /// dependencies which are not required by composer.json
+    Dependencies,
+    AllDependencies,
+}
+
+/// delete the vendor directories of packages, and their vendor directory once it is empty
+fn remove_vendor_dirs(names: &HashSet<String>) -> Result<(), ComposerError> {
+    let vendor = Path::new("./vendor");
+    for item in names.iter() {
+        let path = vendor.join(item);
+        if path.exists() {
+            remove_dir_all(path)?;
+        }
+    }
+    for item in names.iter() {
+        let path = vendor.join(item);
+        if let Some(parent) = path.parent() {
+            if let Ok(res) = has_files(parent) {
+                if !res {
+                    remove_dir_all(parent)?;
+                }
+            }
+        }
+    }
+
+    fn has_files(path: &Path) -> Result<bool, std::io::Error> {
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_file() || file_type.is_dir() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    Ok(())
+}
+
+/// encode `value` like php's `json_encode` without flags does, slashes and
+/// unicode are escaped and objects with no keys are lists
+fn php_json(value: &Value, out: &mut String) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => out.push_str(&n.to_string()),
+        Value::String(s) => {
+            out.push('"');
+            for c in s.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '/' => out.push_str("\\/"),
+                    '\u{8}' => out.push_str("\\b"),
+                    '\u{c}' => out.push_str("\\f"),
+                    '\n' => out.push_str("\\n"),
+                    '\r' => out.push_str("\\r"),
+                    '\t' => out.push_str("\\t"),
+                    c if (c as u32) < 0x20 || (c as u32) > 0x7f => {
+                        let mut units = [0u16; 2];
+                        for unit in c.encode_utf16(&mut units) {
+                            out.push_str(&format!("\\u{:04x}", unit));
+                        }
+                    }
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+        }
+        Value::Array(list) => {
+            out.push('[');
+            for (i, item) in list.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                php_json(item, out);
+            }
+            out.push(']');
+        }
+        // php arrays with the keys 0, 1, 2... are lists
+        Value::Object(map) if map.keys().enumerate().all(|(i, k)| *k == i.to_string()) => {
+            let list = map.values().cloned().collect();
+            php_json(&Value::Array(list), out);
+        }
+        Value::Object(map) => {
+            out.push('{');
+            for (i, (key, item)) in map.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                php_json(&Value::String(key.to_owned()), out);
+                out.push(':');
+                php_json(item, out);
+            }
+            out.push('}');
+        }
+    }
+}
@@ -0,0 +1,569 @@
+//! edit composer.json in place
+//!
+//! Only the touched keys are rewritten, everything else, like the key order,
+//! the indentation, unknown fields and the trailing newline, is kept as it is.
+
+use std::ops::Range;
+
+use serde::Serialize;
+use serde_json::{ser::PrettyFormatter, Serializer, Value};
+
+use crate::error::ComposerError;
+
+const DEFAULT_INDENT: &str = "    ";
+
+pub(crate) struct JsonManipulator {
+    contents: String,
+    indent: String,
+    newline: &'static str,
+}
+
+/// a `"key": value` pair of an object
+#[derive(Debug)]
+struct Member {
+    key: String,
+    /// the opening quote of the key
+    start: usize,
+    value: Range<usize>,
+}
+
+/// the members of an object, and where its braces are
+#[derive(Debug)]
+struct Object {
+    open: usize,
+    close: usize,
+    members: Vec<Member>,
+}
+
+impl JsonManipulator {
+    pub fn new(contents: &str) -> Result<Self, ComposerError> {
+        let value: Value = serde_json::from_str(contents)?;
+        if !value.is_object() {
+            return Err(ComposerError::InvalidJson(contents.to_owned()));
+        }
+
+        Ok(Self {
+            indent: detect_indent(contents),
+            newline: if contents.contains("\r\n") {
+                "\r\n"
+            } else {
+                "\n"
+            },
+            contents: contents.to_owned(),
+        })
+    }
+
+    pub fn contents(&self) -> &str {
+        &self.contents
+    }
+
+    /// set `name` to `constraint` in a link section like `require`, new links are
+    /// appended, or put in order with `sort_packages`
+    pub fn add_link(
+        &mut self,
+        section: &str,
+        name: &str,
+        constraint: &str,
+        sort_packages: bool,
+    ) -> Result<(), ComposerError> {
+        let value = Value::String(constraint.to_owned());
+        match self.main_object(section)? {
+            Some(object) => {
+                self.set_member(object, name, &value, sort_packages.then_some(link_order))
+            }
+            None => {
+                let mut links = serde_json::Map::new();
+                links.insert(name.to_owned(), value);
+                self.add_main_key(section, &Value::Object(links))
+            }
+        }
+    }
+
+    /// remove `name` from the object at the main key `main`
+    pub fn remove_sub_node(&mut self, main: &str, name: &str) -> Result<bool, ComposerError> {
+        match self.main_object(main)? {
+            Some(object) => Ok(self.remove_member(&object, name)),
+            None => Ok(false),
+        }
+    }
+
+    /// set a main key, a new one is appended
+    pub fn add_main_key(&mut self, key: &str, value: &Value) -> Result<(), ComposerError> {
+        let root = self.root()?;
+        self.set_member(root, key, value, None)
+    }
+
+    pub fn remove_main_key(&mut self, key: &str) -> Result<bool, ComposerError> {
+        let root = self.root()?;
+        Ok(self.remove_member(&root, key))
+    }
+
+    fn root(&self) -> Result<Object, ComposerError> {
+        let start = skip_whitespace(&self.contents, 0);
+        parse_object(&self.contents, start).ok_or(self.invalid())
+    }
+
+    /// the object at the main key `key`, `None` when there is no such key
+    fn main_object(&self, key: &str) -> Result<Option<Object>, ComposerError> {
+        let root = self.root()?;
+        let member = match root.members.iter().find(|m| m.key == key) {
+            Some(member) => member,
+            None => return Ok(None),
+        };
+        match parse_object(&self.contents, member.value.start) {
+            Some(object) => Ok(Some(object)),
+            None => Err(self.invalid()),
+        }
+    }
+
+    fn invalid(&self) -> ComposerError {
+        ComposerError::InvalidJson(self.contents.clone())
+    }
+
+    /// replace the value of `key` in `object`, or insert it, at the end or before
+    /// the first key which comes after it in `order`
+    fn set_member(
+        &mut self,
+        object: Object,
+        key: &str,
+        value: &Value,
+        order: Option<fn(&str, &str) -> std::cmp::Ordering>,
+    ) -> Result<(), ComposerError> {
+        let multiline = self.contents[object.open..object.close].contains('\n');
+
+        if let Some(member) = object.members.iter().find(|m| m.key == key) {
+            let indent = line_indent(&self.contents, member.start).to_owned();
+            let value = self.format(value, multiline.then_some(&indent))?;
+            self.contents.replace_range(member.value.clone(), &value);
+            return Ok(());
+        }
+
+        let quoted = serde_json::to_string(key)?;
+        let newline = self.newline;
+
+        let last = match object.members.last() {
+            Some(last) => last,
+            None => {
+                // an empty object, which may span several lines
+                let outer = line_indent(&self.contents, object.open).to_owned();
+                let inner = format!("{}{}", outer, self.indent);
+                let value = self.format(value, Some(&inner))?;
+                self.contents.replace_range(
+                    object.open + 1..object.close,
+                    &format!("{newline}{inner}{quoted}: {value}{newline}{outer}"),
+                );
+                return Ok(());
+            }
+        };
+
+        let inner = line_indent(&self.contents, last.start).to_owned();
+        let value = self.format(value, multiline.then_some(&inner))?;
+
+        let before =
+            order.and_then(|order| object.members.iter().find(|m| order(&m.key, key).is_gt()));
+        match before {
+            Some(member) if multiline => self
+                .contents
+                .insert_str(member.start, &format!("{quoted}: {value},{newline}{inner}")),
+            Some(member) => self
+                .contents
+                .insert_str(member.start, &format!("{quoted}: {value}, ")),
+            None if multiline => self.contents.insert_str(
+                last.value.end,
+                &format!(",{newline}{inner}{quoted}: {value}"),
+            ),
+            None => self
+                .contents
+                .insert_str(last.value.end, &format!(", {quoted}: {value}")),
+        }
+
+        Ok(())
+    }
+
+    fn remove_member(&mut self, object: &Object, key: &str) -> bool {
+        let index = match object.members.iter().position(|m| m.key == key) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let range = if object.members.len() == 1 {
+            object.open + 1..object.close
+        } else if index == 0 {
+            object.members[0].start..object.members[1].start
+        } else {
+            object.members[index - 1].value.end..object.members[index].value.end
+        };
+        self.contents.replace_range(range, "");
+
+        true
+    }
+
+    /// pretty print `value`, its lines after the first one start with `indent`,
+    /// or print it on one line without `indent`
+    fn format(&self, value: &Value, indent: Option<&String>) -> Result<String, ComposerError> {
+        let indent = match indent {
+            Some(indent) => indent,
+            None => return Ok(serde_json::to_string(value)?),
+        };
+
+        let mut buf = Vec::new();
+        let formatter = PrettyFormatter::with_indent(self.indent.as_bytes());
+        let mut ser = Serializer::with_formatter(&mut buf, formatter);
+        value.serialize(&mut ser)?;
+
+        let json = String::from_utf8(buf).map_err(|_| self.invalid())?;
+
+        Ok(json
+            .lines()
+            .collect::<Vec<_>>()
+            .join(&format!("{}{}", self.newline, indent)))
+    }
+}
+
+/// the order composer uses for `sort-packages`, platform packages first
+fn link_order(a: &str, b: &str) -> std::cmp::Ordering {
+    fn rank(name: &str) -> u8 {
+        if name == "php" {
+            0
+        } else if name == "hhvm" {
+            1
+        } else if name.starts_with("ext-") {
+            2
+        } else if name.starts_with("lib-") {
+            3
+        } else {
+            4
+        }
+    }
+
+    rank(a)
+        .cmp(&rank(b))
+        .then_with(|| a.to_lowercase().cmp(&b.to_lowercase()))
+}
+
+/// the indentation of the first indented key
+fn detect_indent(contents: &str) -> String {
+    for line in contents.lines() {
+        let trimmed = line.trim_start_matches([' ', '\t']);
+        if trimmed.len() < line.len() && trimmed.starts_with('"') {
+            return line[..line.len() - trimmed.len()].to_owned();
+        }
+    }
+    DEFAULT_INDENT.to_owned()
+}
+
+/// the whitespace at the start of the line containing `pos`
+fn line_indent(contents: &str, pos: usize) -> &str {
+    let start = contents[..pos].rfind('\n').map_or(0, |i| i + 1);
+    let line = &contents[start..];
+    let trimmed = line.trim_start_matches([' ', '\t']);
+    &line[..line.len() - trimmed.len()]
+}
+
+fn skip_whitespace(contents: &str, mut pos: usize) -> usize {
+    let bytes = contents.as_bytes();
+    while pos < bytes.len() && matches!(bytes[pos], b' ' | b'\t' | b'\n' | b'\r') {
+        pos += 1;
+    }
+    pos
+}
+
+/// the end of the string starting with the quote at `pos`
+fn scan_string(contents: &str, pos: usize) -> Option<usize> {
+    let bytes = contents.as_bytes();
+    if bytes.get(pos) != Some(&b'"') {
+        return None;
+    }
+    let mut i = pos + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return Some(i + 1),
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// the end of the value starting at `pos`
+fn scan_value(contents: &str, pos: usize) -> Option<usize> {
+    let bytes = contents.as_bytes();
+    match bytes.get(pos)? {
+        b'"' => scan_string(contents, pos),
+        b'{' | b'[' => {
+            let mut depth = 0;
+            let mut i = pos;
+            while i < bytes.len() {
+                match bytes[i] {
+                    b'"' => {
+                        i = scan_string(contents, i)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(i + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+            None
+        }
+        _ => {
+            let len = contents[pos..]
+                .find(|c: char| matches!(c, ',' | '}' | ']') || c.is_whitespace())
+                .unwrap_or(contents.len() - pos);
+            Some(pos + len)
+        }
+    }
+}
+
+/// the object starting with the brace at `pos`
+fn parse_object(contents: &str, pos: usize) -> Option<Object> {
+    if contents.as_bytes().get(pos) != Some(&b'{') {
+        return None;
+    }
+
+    let mut members = Vec::new();
+    let mut i = skip_whitespace(contents, pos + 1);
+    loop {
+        match contents.as_bytes().get(i)? {
+            b'}' => {
+                return Some(Object {
+                    open: pos,
+                    close: i,
+                    members,
+                })
+            }
+            b',' => i = skip_whitespace(contents, i + 1),
+            b'"' => {
+                let key_end = scan_string(contents, i)?;
+                let key: String = serde_json::from_str(&contents[i..key_end]).ok()?;
+                let colon = skip_whitespace(contents, key_end);
+                if contents.as_bytes().get(colon) != Some(&b':') {
+                    return None;
+                }
+                let value_start = skip_whitespace(contents, colon + 1);
+                let value_end = scan_value(contents, value_start)?;
+                members.push(Member {
+                    key,
+                    start: i,
+                    value: value_start..value_end,
+                });
+                i = skip_whitespace(contents, value_end);
+            }
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const COMPOSER: &str = r#"{
+    "name": "foo/app",
+    "description": "An app",
+    "require": {
+        "php": ">=8.1",
+        "foo/bar": "^1.0"
+    },
+    "scripts": {
+        "test": "phpunit"
+    },
+    "config": {
+        "sort-packages": true
+    }
+}
+"#;
+
+    #[test]
+    fn test_unchanged() {
+        let json = JsonManipulator::new(COMPOSER).unwrap();
+        assert_eq!(json.contents(), COMPOSER);
+
+        let mut json = JsonManipulator::new(COMPOSER).unwrap();
+        json.add_link("require", "foo/bar", "^1.0", false).unwrap();
+        assert_eq!(json.contents(), COMPOSER);
+    }
+
+    #[test]
+    fn test_add_link() {
+        let mut json = JsonManipulator::new(COMPOSER).unwrap();
+        json.add_link("require", "foo/baz", "^2.0", false).unwrap();
+        json.add_link("require", "foo/bar", "^1.2", false).unwrap();
+        assert_eq!(
+            json.contents(),
+            r#"{
+    "name": "foo/app",
+    "description": "An app",
+    "require": {
+        "php": ">=8.1",
+        "foo/bar": "^1.2",
+        "foo/baz": "^2.0"
+    },
+    "scripts": {
+        "test": "phpunit"
+    },
+    "config": {
+        "sort-packages": true
+    }
+}
+"#
+        );
+
+        let mut json = JsonManipulator::new(COMPOSER).unwrap();
+        json.add_link("require", "ext-json", "*", true).unwrap();
+        json.add_link("require", "foo/app", "^1.0", true).unwrap();
+        json.add_link("require", "foo/zzz", "^1.0", true).unwrap();
+        assert!(json.contents().contains(
+            r#"
+        "php": ">=8.1",
+        "ext-json": "*",
+        "foo/app": "^1.0",
+        "foo/bar": "^1.0",
+        "foo/zzz": "^1.0"
+    },"#
+        ));
+    }
+
+    #[test]
+    fn test_add_section() {
+        let mut json = JsonManipulator::new(COMPOSER).unwrap();
+        json.add_link("require-dev", "foo/test", "^1.0", false)
+            .unwrap();
+        assert!(json.contents().ends_with(
+            r#"    "config": {
+        "sort-packages": true
+    },
+    "require-dev": {
+        "foo/test": "^1.0"
+    }
+}
+"#
+        ));
+
+        let mut json = JsonManipulator::new("{}").unwrap();
+        json.add_link("require", "foo/bar", "^1.0", false).unwrap();
+        assert_eq!(
+            json.contents(),
+            "{\n    \"require\": {\n        \"foo/bar\": \"^1.0\"\n    }\n}"
+        );
+
+        let mut json = JsonManipulator::new("{\n  \"require\": {\n  }\n}\n").unwrap();
+        json.add_link("require", "foo/bar", "^1.0", false).unwrap();
+        assert_eq!(
+            json.contents(),
+            "{\n  \"require\": {\n    \"foo/bar\": \"^1.0\"\n  }\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_indent_and_newline() {
+        let tabs = "{\r\n\t\"require\": {\r\n\t\t\"foo/bar\": \"^1.0\"\r\n\t}\r\n}";
+        let mut json = JsonManipulator::new(tabs).unwrap();
+        json.add_link("require", "foo/baz", "^2.0", false).unwrap();
+        assert_eq!(
+            json.contents(),
+            "{\r\n\t\"require\": {\r\n\t\t\"foo/bar\": \"^1.0\",\r\n\t\t\"foo/baz\": \"^2.0\"\r\n\t}\r\n}"
+        );
+
+        let compact = r#"{"require":{"foo/bar":"^1.0"},"name":"foo/app"}"#;
+        let mut json = JsonManipulator::new(compact).unwrap();
+        json.add_link("require", "foo/baz", "^2.0", false).unwrap();
+        assert_eq!(
+            json.contents(),
+            r#"{"require":{"foo/bar":"^1.0", "foo/baz": "^2.0"},"name":"foo/app"}"#
+        );
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut json = JsonManipulator::new(COMPOSER).unwrap();
+        assert!(json.remove_sub_node("require", "php").unwrap());
+        assert!(!json.remove_sub_node("require", "php").unwrap());
+        assert!(!json.remove_sub_node("require-dev", "php").unwrap());
+        assert!(json.contents().contains(
+            r#""require": {
+        "foo/bar": "^1.0"
+    },"#
+        ));
+        assert!(json.remove_sub_node("require", "foo/bar").unwrap());
+        assert!(json.contents().contains(r#""require": {},"#));
+
+        let mut json = JsonManipulator::new(COMPOSER).unwrap();
+        assert!(json.remove_sub_node("require", "foo/bar").unwrap());
+        assert!(json.contents().contains(
+            r#""require": {
+        "php": ">=8.1"
+    },"#
+        ));
+
+        let mut json = JsonManipulator::new(COMPOSER).unwrap();
+        assert!(json.remove_main_key("config").unwrap());
+        assert!(json.remove_main_key("name").unwrap());
+        assert!(json.contents().starts_with("{\n    \"description\""));
+        assert!(json
+            .contents()
+            .ends_with("        \"test\": \"phpunit\"\n    }\n}\n"));
+        assert_eq!(
+            serde_json::from_str::<Value>(json.contents()).unwrap(),
+            json!({
+                "description": "An app",
+                "require": {"php": ">=8.1", "foo/bar": "^1.0"},
+                "scripts": {"test": "phpunit"}
+            })
+        );
+    }
+
+    #[test]
+    fn test_main_key() {
+        let mut json = JsonManipulator::new(COMPOSER).unwrap();
+        json.add_main_key(
+            "scripts",
+            &json!({"test": "phpunit", "lint": ["phpcs", "phpstan"]}),
+        )
+        .unwrap();
+        json.add_main_key("minimum-stability", &json!("dev"))
+            .unwrap();
+        assert_eq!(
+            json.contents(),
+            r#"{
+    "name": "foo/app",
+    "description": "An app",
+    "require": {
+        "php": ">=8.1",
+        "foo/bar": "^1.0"
+    },
+    "scripts": {
+        "test": "phpunit",
+        "lint": [
+            "phpcs",
+            "phpstan"
+        ]
+    },
+    "config": {
+        "sort-packages": true
+    },
+    "minimum-stability": "dev"
+}
+"#
+        );
+
+        let strings = r#"{"name": "a \"quoted\" {name}", "extra": {"x": [1, {"y": "}"}]}}"#;
+        let mut json = JsonManipulator::new(strings).unwrap();
+        json.add_main_key("extra", &json!(null)).unwrap();
+        assert_eq!(
+            json.contents(),
+            r#"{"name": "a \"quoted\" {name}", "extra": null}"#
+        );
+
+        assert!(JsonManipulator::new("[]").is_err());
+        assert!(JsonManipulator::new("{").is_err());
+    }
+}
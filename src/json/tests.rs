@@ -7,18 +7,30 @@ use crate::io::tests::TestWriter;
 use super::*;
 
 fn get_repositories(url: String) -> Repositories {
-    Repositories {
-        packagist: Packagist {
-            _type: "composer".to_owned(),
-            url,
-        },
-    }
+    let mut repositories = Repositories::default();
+    repositories.set(PACKAGIST, "composer", &url);
+    repositories
 }
-fn default_context(composer: &Composer) -> Arc<Mutex<Context>> {
-    let p2_url = composer.get_package_url().unwrap();
+thread_local! {
+    /// the caches of the contexts, removed when the test, and its thread, ends
+    static CACHE_DIRS: std::cell::RefCell<Vec<tempfile::TempDir>> = const {
+        std::cell::RefCell::new(Vec::new())
+    };
+}
+fn new_context(composer: &Composer) -> Context {
+    let repositories = composer.repositories().unwrap();
     let mut context = Context::new().unwrap();
-    context.p2_url = p2_url;
-    Arc::new(Mutex::new(context))
+    context.repositories = repositories;
+    // mock servers reuse ports, so every test needs its own cache
+    let cache_dir = tempfile::tempdir().unwrap();
+    context.cache_dir = cache_dir.path().to_path_buf();
+    CACHE_DIRS.with(|dirs| dirs.borrow_mut().push(cache_dir));
+    // mock servers only answer plain http
+    context.http = Http::insecure();
+    context
+}
+fn default_context(composer: &Composer) -> Arc<Mutex<Context>> {
+    Arc::new(Mutex::new(new_context(composer)))
 }
 
 #[tokio::test]
@@ -54,7 +66,7 @@ async fn simple() {
     hello_mock.assert();
     let version = &lock.packages[0];
     assert_eq!(version.version, "1.2.3".to_owned());
-    assert!(stderr.output().is_empty())
+    assert_eq!(stderr.output(), "  - Locking foo/bar(1.2.3)")
 }
 
 #[tokio::test]
@@ -111,7 +123,219 @@ async fn one_depend() {
     let bar2_version = &lock.packages[1];
     assert_eq!(bar2_version.version, "2.3.0".to_owned());
 
-    assert!(stderr.output().is_empty())
+    assert_eq!(
+        stderr.output(),
+        "  - Locking foo/bar(1.2.3)  - Locking foo2/bar2(2.3.0)"
+    )
+}
+
+#[tokio::test]
+async fn concurrent_fetch() {
+    use crate::http::tests::ParallelServer;
+
+    // the root requirements are fetched at once
+    let server = ParallelServer::start(3, |path| {
+        let name = path.strip_prefix("/p2/")?.strip_suffix(".json")?;
+        let mut version = json!({"version": "1.0.0", "version_normalized": "1.0.0.0"});
+        match name {
+            "foo/a" | "foo/b" | "foo/c" => {
+                version["require"] = json!({"foo/shared": "^1.0"});
+            }
+            "foo/shared" => {}
+            _ => return None,
+        }
+        Some(
+            json!({ "packages": { name: [version] } })
+                .to_string()
+                .into_bytes(),
+        )
+    });
+
+    let composer = Composer {
+        require: Some({
+            let mut map = IndexMap::new();
+            map.insert("foo/a".to_owned(), "^1.0".to_owned());
+            map.insert("foo/b".to_owned(), "^1.0".to_owned());
+            map.insert("foo/c".to_owned(), "^1.0".to_owned());
+            map
+        }),
+        repositories: Some(get_repositories(server.url(""))),
+        ..Default::default()
+    };
+    let mut stderr = TestWriter::new();
+    let ctx = default_context(&composer);
+
+    let lock = composer.get_lock(&mut stderr, ctx).await.unwrap();
+    assert_eq!(server.peak(), 3);
+    for name in ["foo/a", "foo/b", "foo/c", "foo/shared"] {
+        assert_eq!(server.hits(&format!("/p2/{name}.json")), 1);
+    }
+    assert_eq!(lock.packages.len(), 4);
+}
+
+#[tokio::test]
+async fn revalidate() {
+    let server = MockServer::start();
+    let body = json!({
+        "packages" : {
+            "foo/bar" : [{
+                "version" : "1.2.3",
+                "version_normalized": "1.2.3.0",
+            }]
+        }
+    });
+    let last_modified = "Wed, 21 Oct 2015 07:28:00 GMT";
+
+    let mut first = server.mock(|when, then| {
+        when.method(GET).path("/p2/foo/bar.json");
+        then.status(200)
+            .header("Last-Modified", last_modified)
+            .json_body(body.clone());
+    });
+
+    let composer = Composer {
+        require: Some({
+            let mut map = IndexMap::new();
+            map.insert("foo/bar".to_owned(), "^1.0".to_owned());
+            map
+        }),
+        repositories: Some(get_repositories(server.base_url())),
+        ..Default::default()
+    };
+    let mut stderr = TestWriter::new();
+    let context = new_context(&composer);
+    let cache_dir = context.cache_dir.clone();
+    composer
+        .get_lock(&mut stderr, Arc::new(Mutex::new(context)))
+        .await
+        .unwrap();
+    first.assert();
+    first.delete();
+
+    let not_modified = server.mock(|when, then| {
+        when.method(GET)
+            .path("/p2/foo/bar.json")
+            .header("If-Modified-Since", last_modified);
+        then.status(304);
+    });
+
+    // revalidated, and the cached file is used
+    let mut context = new_context(&composer);
+    context.cache_dir = cache_dir.clone();
+    let lock = composer
+        .get_lock(&mut stderr, Arc::new(Mutex::new(context)))
+        .await
+        .unwrap();
+    not_modified.assert();
+    assert_eq!(lock.packages[0].version, "1.2.3");
+
+    // validated in the last hour, or never revalidated
+    for ttl in [Some(Duration::from_secs(3600)), None] {
+        let mut context = new_context(&composer);
+        context.cache_dir = cache_dir.clone();
+        context.metadata_ttl = ttl;
+        composer
+            .get_lock(&mut stderr, Arc::new(Mutex::new(context)))
+            .await
+            .unwrap();
+        not_modified.assert();
+    }
+}
+
+#[tokio::test]
+async fn minified() {
+    let server = MockServer::start();
+
+    server.mock(|when, then| {
+        when.method(GET).path("/p2/foo/bar.json");
+        then.status(200).json_body(json!({
+            "minified": "composer/2.0",
+            "packages" : {
+                "foo/bar" : [{
+                    "name" : "foo/bar",
+                    "version" : "2.0.0",
+                    "version_normalized": "2.0.0.0",
+                    "require": { "foo/log": "^1.0" },
+                }, {
+                    "version" : "1.1.0",
+                    "version_normalized": "1.1.0.0",
+                }, {
+                    "version" : "1.0.0",
+                    "version_normalized": "1.0.0.0",
+                    "require": "__unset",
+                }]
+            }
+        }));
+    });
+    let dev = server.mock(|when, then| {
+        when.method(GET).path("/p2/foo/bar~dev.json");
+        then.status(200).json_body(json!({
+            "minified": "composer/2.0",
+            "packages" : {
+                "foo/bar" : [{
+                    "name" : "foo/bar",
+                    "version" : "dev-main",
+                    "version_normalized": "dev-main",
+                }]
+            }
+        }));
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/p2/foo/log.json");
+        then.status(200).json_body(json!({
+            "packages" : {
+                "foo/log" : [{
+                    "version" : "1.0.0",
+                    "version_normalized": "1.0.0.0",
+                }]
+            }
+        }));
+    });
+
+    let mut composer = Composer {
+        require: Some({
+            let mut map = IndexMap::new();
+            map.insert("foo/bar".to_owned(), "~1.1".to_owned());
+            map
+        }),
+        repositories: Some(get_repositories(server.base_url())),
+        ..Default::default()
+    };
+    let mut stderr = TestWriter::new();
+
+    // 1.1.0 keeps the requirements of 2.0.0
+    let lock = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap();
+    assert_eq!(lock.packages.len(), 2);
+    assert_eq!(lock.packages[0].version, "1.1.0");
+    dev.assert_hits(0);
+
+    composer
+        .require
+        .as_mut()
+        .unwrap()
+        .insert("foo/bar".to_owned(), "1.0.0".to_owned());
+    let lock = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap();
+    assert_eq!(lock.packages.len(), 1);
+    dev.assert_hits(0);
+
+    // dev versions are only fetched when they are allowed
+    composer
+        .require
+        .as_mut()
+        .unwrap()
+        .insert("foo/bar".to_owned(), "dev-main".to_owned());
+    let lock = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap();
+    assert_eq!(lock.packages[0].version, "dev-main");
+    dev.assert_hits(1);
 }
 
 #[tokio::test]
@@ -151,7 +375,7 @@ async fn last_stable() {
     hello_mock.assert();
     let version = &lock.packages[0];
     assert_eq!(version.version, "1.2.3".to_owned());
-    assert!(stderr.output().is_empty())
+    assert_eq!(stderr.output(), "  - Locking foo/bar(1.2.3)")
 }
 
 #[tokio::test]
@@ -191,41 +415,33 @@ async fn php_version() {
         ..Default::default()
     };
     let mut stderr = TestWriter::new();
-    let p2_url = composer.get_package_url().unwrap();
-    let mut context = Context::new().unwrap();
-    context.p2_url = p2_url;
-    context.php_version = "8.2.0".to_owned();
+    let mut context = new_context(&composer);
+    context.php_version = "7.4.0".to_owned();
     let ctx = Arc::new(Mutex::new(context));
 
     let error = composer.get_lock(&mut stderr, ctx).await.unwrap_err();
-    assert!(matches!(error, ComposerError::PhpVersion));
+    assert!(matches!(error, ComposerError::Unresolvable));
     hello_mock.assert();
 
     assert_eq!(
         stderr.output(),
-        "foo/bar(*) -> .. -> foo/bar(1.2.3) need PHP version is >=8.3.0"
+        "Your requirements could not be resolved to an installable set of packages.
+  - composer.json requires foo/bar(*), but no version of foo/bar can be installed:
+    - foo/bar(1.2.3) requires php(>=8.3.0), but your PHP version is 7.4.0
+    - foo/bar(1.1.0) requires php(>=8.0.0), but your PHP version is 7.4.0"
     );
 
-    // let mut composer = Composer {
-    //     require: Some({
-    //         let mut map = IndexMap::new();
-    //         map.insert("foo/bar".to_owned(), "1.1.0".to_owned());
-    //         map
-    //     }),
-    //     repositories: Some(get_repositories(server.base_url())),
-    // };
-    // let mut stderr = TestWriter::new();
-    // let p2_url = composer.get_package_url().unwrap();
-    // let mut context = Context::new().unwrap();
-    // context.p2_url = p2_url;
-    // context.php_version = "8.2.0".to_owned();
-    // let ctx = Arc::new(Mutex::new(context));
-
-    // let _ = composer.get_lock(&mut stderr, ctx).await.unwrap();
+    // fall back to the older version which supports the php version
+    let mut stderr = TestWriter::new();
+    let mut context = new_context(&composer);
+    context.php_version = "8.2.0".to_owned();
+    let ctx = Arc::new(Mutex::new(context));
 
-    // hello_mock.assert();
+    let lock = composer.get_lock(&mut stderr, ctx).await.unwrap();
 
-    // assert!(stderr.output().is_empty());
+    hello_mock.assert_hits(2);
+    assert_eq!(lock.packages[0].version, "1.1.0".to_owned());
+    assert_eq!(stderr.output(), "  - Locking foo/bar(1.1.0)");
 }
 
 #[tokio::test]
@@ -258,19 +474,19 @@ async fn php_extensions() {
         ..Default::default()
     };
     let mut stderr = TestWriter::new();
-    let p2_url = composer.get_package_url().unwrap();
-    let mut context = Context::new().unwrap();
-    context.p2_url = p2_url;
+    let mut context = new_context(&composer);
     context.php_extensions = vec![];
     let ctx = Arc::new(Mutex::new(context));
 
     let error = composer.get_lock(&mut stderr, ctx).await.unwrap_err();
-    assert!(matches!(error, ComposerError::PhpVersion));
+    assert!(matches!(error, ComposerError::Unresolvable));
     hello_mock.assert();
 
     assert_eq!(
         stderr.output(),
-        "foo/bar(*) -> .. -> foo/bar(1.2.3) need ext-dom,it is missing from your system. Install or enable PHP's dom extension."
+        "Your requirements could not be resolved to an installable set of packages.
+  - composer.json requires foo/bar(*), but no version of foo/bar can be installed:
+    - foo/bar(1.2.3) requires ext-dom, it is missing from your system. Install or enable PHP's dom extension."
     );
 }
 
@@ -312,5 +528,1636 @@ async fn auto_choise_version() {
     assert_eq!(lock.packages.len(), 1);
     let version = &lock.packages[0];
     assert_eq!(version.version, "1.2.3".to_owned());
-    assert!(stderr.output().is_empty())
+    assert_eq!(stderr.output(), "  - Locking foo/bar(1.2.3)")
+}
+
+#[tokio::test]
+async fn stricter_constraint() {
+    let server = MockServer::start();
+
+    let bar = server.mock(|when, then| {
+        when.method(GET).path("/p2/foo/bar.json");
+        then.status(200).json_body(json!({
+            "packages" : {
+                "foo/bar" : [{
+                    "name" : "foo/bar",
+                    "version" : "1.0.0",
+                    "version_normalized": "1.0.0.0",
+                    "require":{
+                        "foo/log" : "^1.0 || ^2.0",
+                    }
+                }]
+            }
+        }));
+    });
+    let baz = server.mock(|when, then| {
+        when.method(GET).path("/p2/foo/baz.json");
+        then.status(200).json_body(json!({
+            "packages" : {
+                "foo/baz" : [{
+                    "name" : "foo/baz",
+                    "version" : "1.0.0",
+                    "version_normalized": "1.0.0.0",
+                    "require":{
+                        "foo/log" : "^1.0",
+                    }
+                }]
+            }
+        }));
+    });
+    let log = server.mock(|when, then| {
+        when.method(GET).path("/p2/foo/log.json");
+        then.status(200).json_body(json!({
+            "packages" : {
+                "foo/log" : [{
+                    "name" : "foo/log",
+                    "version" : "2.0.0",
+                    "version_normalized": "2.0.0.0",
+                },{
+                    "name" : "foo/log",
+                    "version" : "1.1.0",
+                    "version_normalized": "1.1.0.0",
+                }]
+            }
+        }));
+    });
+
+    let composer = Composer {
+        require: Some({
+            let mut map = IndexMap::new();
+            map.insert("foo/bar".to_owned(), "^1.0".to_owned());
+            map.insert("foo/baz".to_owned(), "^1.0".to_owned());
+            map
+        }),
+        repositories: Some(get_repositories(server.base_url())),
+        ..Default::default()
+    };
+    let mut stderr = TestWriter::new();
+    let ctx = default_context(&composer);
+
+    let lock = composer.get_lock(&mut stderr, ctx).await.unwrap();
+    bar.assert();
+    baz.assert();
+    log.assert();
+    assert_eq!(lock.packages.len(), 3);
+    let log_version = lock.find_version("foo/log").unwrap();
+    assert_eq!(log_version.version, "1.1.0".to_owned());
+    assert_eq!(
+        stderr.output(),
+        "  - Locking foo/bar(1.0.0)  - Locking foo/baz(1.0.0)  - Locking foo/log(1.1.0)"
+    )
+}
+
+#[tokio::test]
+async fn backtracking() {
+    let server = MockServer::start();
+
+    server.mock(|when, then| {
+        when.method(GET).path("/p2/foo/bar.json");
+        then.status(200).json_body(json!({
+            "packages" : {
+                "foo/bar" : [{
+                    "name" : "foo/bar",
+                    "version" : "2.0.0",
+                    "version_normalized": "2.0.0.0",
+                    "require":{
+                        "foo/log" : "^2.0",
+                    }
+                },{
+                    "name" : "foo/bar",
+                    "version" : "1.0.0",
+                    "version_normalized": "1.0.0.0",
+                    "require":{
+                        "foo/log" : "^1.0",
+                    }
+                }]
+            }
+        }));
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/p2/foo/baz.json");
+        then.status(200).json_body(json!({
+            "packages" : {
+                "foo/baz" : [{
+                    "name" : "foo/baz",
+                    "version" : "1.0.0",
+                    "version_normalized": "1.0.0.0",
+                    "require":{
+                        "foo/log" : "^1.0",
+                    }
+                }]
+            }
+        }));
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/p2/foo/log.json");
+        then.status(200).json_body(json!({
+            "packages" : {
+                "foo/log" : [{
+                    "name" : "foo/log",
+                    "version" : "2.0.0",
+                    "version_normalized": "2.0.0.0",
+                },{
+                    "name" : "foo/log",
+                    "version" : "1.0.0",
+                    "version_normalized": "1.0.0.0",
+                }]
+            }
+        }));
+    });
+
+    let composer = Composer {
+        require: Some({
+            let mut map = IndexMap::new();
+            map.insert("foo/bar".to_owned(), ">=1.0".to_owned());
+            map.insert("foo/baz".to_owned(), "^1.0".to_owned());
+            map
+        }),
+        repositories: Some(get_repositories(server.base_url())),
+        ..Default::default()
+    };
+    let mut stderr = TestWriter::new();
+    let ctx = default_context(&composer);
+
+    let lock = composer.get_lock(&mut stderr, ctx).await.unwrap();
+    assert_eq!(lock.find_version("foo/bar").unwrap().version, "1.0.0");
+    assert_eq!(lock.find_version("foo/baz").unwrap().version, "1.0.0");
+    assert_eq!(lock.find_version("foo/log").unwrap().version, "1.0.0");
+    assert_eq!(
+        stderr.output(),
+        "  - Locking foo/bar(1.0.0)  - Locking foo/baz(1.0.0)  - Locking foo/log(1.0.0)"
+    )
+}
+
+/// 25 versions of `name`, each requiring `require`
+fn many_versions(name: &str, require: serde_json::Value) -> serde_json::Value {
+    let versions: Vec<_> = (0..25)
+        .rev()
+        .map(|patch| {
+            json!({
+                "name": name,
+                "version": format!("1.0.{patch}"),
+                "version_normalized": format!("1.0.{patch}.0"),
+                "require": require,
+            })
+        })
+        .collect();
+    json!({ "packages": { name: versions } })
+}
+
+#[tokio::test]
+async fn wide_conflict() {
+    let server = MockServer::start();
+    for (name, require) in [
+        ("foo/a", json!({"foo/log": "^1.0"})),
+        ("foo/b", json!({})),
+        ("foo/c", json!({})),
+        ("foo/d", json!({"foo/log": "^2.0"})),
+        ("foo/e", json!({"foo/z": "^2.0"})),
+    ] {
+        server.mock(|when, then| {
+            when.method(GET).path(format!("/p2/{name}.json"));
+            then.status(200).json_body(many_versions(name, require));
+        });
+    }
+    for (name, version) in [
+        ("foo/log", "1.0.0"),
+        ("foo/log", "2.0.0"),
+        ("foo/z", "1.0.0"),
+    ] {
+        server.mock(|when, then| {
+            when.method(GET).path(format!("/p2/{name}.json"));
+            then.status(200).json_body(json!({
+                "packages": { name: [{"version": version, "version_normalized": format!("{version}.0")}] }
+            }));
+        });
+    }
+
+    // every combination of the other packages fails the same way, none is tried
+    for (conflicting, root, explanation) in [
+        (
+            ["foo/b", "foo/c", "foo/e"],
+            "foo/z",
+            "  - composer.json requires foo/z(^2.0), but no version of foo/z matches it",
+        ),
+        (
+            ["foo/a", "foo/b", "foo/c"],
+            "foo/e",
+            "  - composer.json requires foo/e(*), but no version of foo/e can be installed:
+    - foo/e(1.0.24, 1.0.23, 1.0.22 and 22 more) requires foo/z(^2.0), but no version of foo/z matches it",
+        ),
+        (
+            ["foo/a", "foo/b", "foo/c"],
+            "foo/d",
+            "  - composer.json requires foo/a(*), but no version of foo/a can be installed:
+    - composer.json requires foo/d(*), but no version of foo/d can be installed:
+      - foo/a(1.0.24, 1.0.23, 1.0.22 and 22 more) requires foo/log(^1.0), but foo/d(1.0.24, 1.0.23, 1.0.22 and 22 more) requires foo/log(^2.0), so no version of foo/log satisfies all of them",
+        ),
+    ] {
+        let composer = Composer {
+            require: Some({
+                let mut map = IndexMap::new();
+                for name in conflicting {
+                    map.insert(name.to_owned(), "*".to_owned());
+                }
+                map.insert(
+                    root.to_owned(),
+                    if root == "foo/z" { "^2.0" } else { "*" }.to_owned(),
+                );
+                map
+            }),
+            repositories: Some(get_repositories(server.base_url())),
+            ..Default::default()
+        };
+        let mut stderr = TestWriter::new();
+        let ctx = default_context(&composer);
+
+        let start = std::time::Instant::now();
+        let error = composer.get_lock(&mut stderr, ctx).await.unwrap_err();
+        assert!(matches!(error, ComposerError::Unresolvable));
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        // one line for the versions failing for the same reason
+        assert_eq!(
+            stderr.output(),
+            format!(
+                "Your requirements could not be resolved to an installable set of packages.\n{}",
+                explanation
+            )
+        );
+    }
+}
+
+#[tokio::test]
+async fn explain_conflict() {
+    let server = MockServer::start();
+
+    server.mock(|when, then| {
+        when.method(GET).path("/p2/foo/bar.json");
+        then.status(200).json_body(json!({
+            "packages" : {
+                "foo/bar" : [{
+                    "name" : "foo/bar",
+                    "version" : "2.0.0",
+                    "version_normalized": "2.0.0.0",
+                    "require":{
+                        "foo/log" : "^3.0",
+                    }
+                }]
+            }
+        }));
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/p2/foo/baz.json");
+        then.status(200).json_body(json!({
+            "packages" : {
+                "foo/baz" : [{
+                    "name" : "foo/baz",
+                    "version" : "1.4.0",
+                    "version_normalized": "1.4.0.0",
+                    "require":{
+                        "foo/log" : "^1.0",
+                    }
+                }]
+            }
+        }));
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/p2/foo/log.json");
+        then.status(200).json_body(json!({
+            "packages" : {
+                "foo/log" : [{
+                    "name" : "foo/log",
+                    "version" : "3.0.0",
+                    "version_normalized": "3.0.0.0",
+                },{
+                    "name" : "foo/log",
+                    "version" : "1.0.0",
+                    "version_normalized": "1.0.0.0",
+                }]
+            }
+        }));
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/p2/foo/missing.json");
+        then.status(404);
+    });
+
+    let composer = Composer {
+        require: Some({
+            let mut map = IndexMap::new();
+            map.insert("foo/bar".to_owned(), "^2.0".to_owned());
+            map.insert("foo/baz".to_owned(), "^1.0".to_owned());
+            map
+        }),
+        repositories: Some(get_repositories(server.base_url())),
+        ..Default::default()
+    };
+    let mut stderr = TestWriter::new();
+    let ctx = default_context(&composer);
+
+    let error = composer.get_lock(&mut stderr, ctx).await.unwrap_err();
+    assert!(matches!(error, ComposerError::Unresolvable));
+    assert_eq!(
+        stderr.output(),
+        "Your requirements could not be resolved to an installable set of packages.
+  - composer.json requires foo/bar(^2.0), but no version of foo/bar can be installed:
+    - composer.json requires foo/baz(^1.0), but no version of foo/baz can be installed:
+      - foo/bar(2.0.0) requires foo/log(^3.0), but foo/baz(1.4.0) requires foo/log(^1.0), so no version of foo/log satisfies all of them"
+    );
+
+    let composer = Composer {
+        require: Some({
+            let mut map = IndexMap::new();
+            map.insert("foo/missing".to_owned(), "^1.0".to_owned());
+            map
+        }),
+        repositories: Some(get_repositories(server.base_url())),
+        ..Default::default()
+    };
+    let mut stderr = TestWriter::new();
+    let ctx = default_context(&composer);
+
+    composer.get_lock(&mut stderr, ctx).await.unwrap_err();
+    assert_eq!(
+        stderr.output(),
+        "Your requirements could not be resolved to an installable set of packages.
+  - composer.json requires foo/missing(^1.0), but foo/missing is not found in any repository"
+    );
+}
+
+fn beta_server(server: &MockServer) -> httpmock::Mock<'_> {
+    server.mock(|when, then| {
+        when.method(GET).path("/p2/foo/bar.json");
+        then.status(200).json_body(json!({
+            "packages" : {
+                "foo/bar" : [{
+                    "name" : "foo/bar",
+                    "version" : "2.0.0-beta1",
+                    "version_normalized": "2.0.0.0-beta1",
+                },{
+                    "name" : "foo/bar",
+                    "version" : "1.2.3",
+                    "version_normalized": "1.2.3.0",
+                },{
+                    "name" : "foo/bar",
+                    "version" : "dev-main",
+                    "version_normalized": "dev-main",
+                }]
+            }
+        }));
+    })
+}
+
+#[tokio::test]
+async fn minimum_stability() {
+    let server = MockServer::start();
+    beta_server(&server);
+
+    let mut composer = Composer {
+        require: Some({
+            let mut map = IndexMap::new();
+            map.insert("foo/bar".to_owned(), ">=1.0".to_owned());
+            map
+        }),
+        repositories: Some(get_repositories(server.base_url())),
+        ..Default::default()
+    };
+
+    let mut stderr = TestWriter::new();
+    let lock = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap();
+    assert_eq!(lock.packages[0].version, "1.2.3");
+    assert_eq!(lock.minimum_stability, "stable");
+
+    composer.minimum_stability = Some("beta".to_owned());
+    let lock = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap();
+    assert_eq!(lock.packages[0].version, "2.0.0-beta1");
+    assert_eq!(lock.minimum_stability, "beta");
+
+    composer.prefer_stable = Some(true);
+    let lock = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap();
+    assert_eq!(lock.packages[0].version, "1.2.3");
+    assert!(lock.prefer_stable);
+
+    composer.minimum_stability = Some("foo".to_owned());
+    let error = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ComposerError::InvalidStability(_)));
+}
+
+#[tokio::test]
+async fn stability_flags() {
+    let server = MockServer::start();
+    beta_server(&server);
+
+    let mut composer = Composer {
+        require: Some({
+            let mut map = IndexMap::new();
+            map.insert("foo/bar".to_owned(), ">=1.0@beta".to_owned());
+            map
+        }),
+        repositories: Some(get_repositories(server.base_url())),
+        ..Default::default()
+    };
+
+    let mut stderr = TestWriter::new();
+    let lock = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap();
+    assert_eq!(lock.packages[0].version, "2.0.0-beta1");
+    assert_eq!(lock.stability_flags.get("foo/bar"), Some(&10));
+
+    composer
+        .insert("foo/bar", &Some("dev-main".to_owned()), false)
+        .unwrap();
+    let lock = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap();
+    assert_eq!(lock.packages[0].version, "dev-main");
+    assert_eq!(lock.stability_flags.get("foo/bar"), Some(&20));
+    assert_eq!(
+        stderr.output(),
+        "  - Locking foo/bar(2.0.0-beta1)  - Locking foo/bar(dev-main)"
+    );
+}
+
+fn branch_server(server: &MockServer) {
+    server.mock(|when, then| {
+        when.method(GET).path("/p2/foo/bar.json");
+        then.status(200).json_body(json!({
+            "packages" : {
+                "foo/bar" : [{
+                    "name" : "foo/bar",
+                    "version" : "dev-main",
+                    "version_normalized": "dev-main",
+                    "extra": {
+                        "branch-alias": {
+                            "dev-main": "2.x-dev"
+                        }
+                    }
+                },{
+                    "name" : "foo/bar",
+                    "version" : "1.2.3",
+                    "version_normalized": "1.2.3.0",
+                }]
+            }
+        }));
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/p2/foo/app.json");
+        then.status(200).json_body(json!({
+            "packages" : {
+                "foo/app" : [{
+                    "name" : "foo/app",
+                    "version" : "1.0.0",
+                    "version_normalized": "1.0.0.0",
+                    "require": {
+                        "foo/bar": "^1.5"
+                    }
+                }]
+            }
+        }));
+    });
+}
+
+#[tokio::test]
+async fn branch_alias() {
+    let server = MockServer::start();
+    branch_server(&server);
+
+    let composer = Composer {
+        require: Some({
+            let mut map = IndexMap::new();
+            map.insert("foo/bar".to_owned(), "^2.0@dev".to_owned());
+            map
+        }),
+        repositories: Some(get_repositories(server.base_url())),
+        ..Default::default()
+    };
+
+    let mut stderr = TestWriter::new();
+    let lock = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap();
+    assert_eq!(lock.packages.len(), 1);
+    assert_eq!(lock.packages[0].version, "dev-main");
+    assert_eq!(lock.packages[0].version_normalized, "dev-main");
+    assert!(lock.json().unwrap().contains("\"branch-alias\""));
+    assert!(lock.aliases.is_empty());
+}
+
+#[tokio::test]
+async fn inline_alias() {
+    let server = MockServer::start();
+    branch_server(&server);
+
+    let mut composer = Composer {
+        require: Some({
+            let mut map = IndexMap::new();
+            map.insert("foo/bar".to_owned(), "dev-main".to_owned());
+            map.insert("foo/app".to_owned(), "^1.0".to_owned());
+            map
+        }),
+        repositories: Some(get_repositories(server.base_url())),
+        ..Default::default()
+    };
+
+    let mut stderr = TestWriter::new();
+    let error = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ComposerError::Unresolvable));
+    assert_eq!(
+        stderr.output(),
+        "Your requirements could not be resolved to an installable set of packages.
+  - composer.json requires foo/bar(dev-main), but no version of foo/bar can be installed:
+    - composer.json requires foo/app(^1.0), but no version of foo/app can be installed:
+      - composer.json requires foo/bar(dev-main), but foo/app(1.0.0) requires foo/bar(^1.5), so no version of foo/bar satisfies all of them"
+    );
+
+    composer
+        .insert("foo/bar", &Some("dev-main as 1.5.0".to_owned()), false)
+        .unwrap();
+    let mut stderr = TestWriter::new();
+    let lock = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap();
+    let version = lock.find_version("foo/bar").unwrap();
+    assert_eq!(version.version, "dev-main");
+    assert_eq!(
+        lock.aliases,
+        vec![Alias {
+            package: "foo/bar".to_owned(),
+            version: "dev-main".to_owned(),
+            alias: "1.5.0".to_owned(),
+            alias_normalized: "1.5.0.0".to_owned(),
+        }]
+    );
+}
+
+#[tokio::test]
+async fn stale_lock() {
+    let server = MockServer::start();
+    branch_server(&server);
+
+    let mut composer = Composer {
+        require: Some({
+            let mut map = IndexMap::new();
+            map.insert("php".to_owned(), ">=7.0".to_owned());
+            map.insert("foo/bar".to_owned(), "dev-main as 1.5.0".to_owned());
+            map.insert("foo/app".to_owned(), "^1.0".to_owned());
+            map
+        }),
+        repositories: Some(get_repositories(server.base_url())),
+        ..Default::default()
+    };
+
+    let mut stderr = TestWriter::new();
+    let lock = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap();
+    let lock: ComposerLock = serde_json::from_str(&lock.json().unwrap()).unwrap();
+    assert!(composer.lock_problems(&lock).is_empty());
+
+    composer
+        .insert("foo/bar", &Some("^1.5".to_owned()), false)
+        .unwrap();
+    assert!(composer.lock_problems(&lock).is_empty());
+
+    composer
+        .insert("foo/bar", &Some("^2.0@dev".to_owned()), false)
+        .unwrap();
+    assert!(composer.lock_problems(&lock).is_empty());
+
+    composer
+        .insert("foo/bar", &Some("^3.0".to_owned()), false)
+        .unwrap();
+    composer.insert("foo/baz", &None, false).unwrap();
+    assert_eq!(
+        composer.lock_problems(&lock),
+        vec![
+            "Required package \"foo/bar\" is in the lock file as \"dev-main\" but that does not satisfy your constraint \"^3.0\".",
+            "Required package \"foo/baz\" is not present in the lock file.",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn partial_update() {
+    let server = MockServer::start();
+    let mut mocks = Vec::new();
+    for (name, require) in [
+        ("foo/app", json!({"foo/bar": "^1.0", "foo/log": "^1.0"})),
+        ("foo/bar", json!({})),
+        ("foo/log", json!({})),
+    ] {
+        mocks.push(server.mock(|when, then| {
+            when.method(GET).path(format!("/p2/{}.json", name));
+            then.status(200).json_body(json!({
+                "packages" : {
+                    name : [{
+                        "name" : name,
+                        "version" : "1.1.0",
+                        "version_normalized": "1.1.0.0",
+                        "require": require,
+                    },{
+                        "name" : name,
+                        "version" : "1.0.0",
+                        "version_normalized": "1.0.0.0",
+                        "require": require,
+                    }]
+                }
+            }));
+        }));
+    }
+
+    let lock: ComposerLock = serde_json::from_value(json!({
+        "packages": [{
+            "name": "foo/app",
+            "version": "1.0.0",
+            "version_normalized": "1.0.0.0",
+            "require": {"foo/bar": "^1.0", "foo/log": "^1.0"},
+        },{
+            "name": "foo/bar",
+            "version": "1.0.0",
+            "version_normalized": "1.0.0.0",
+        },{
+            "name": "foo/log",
+            "version": "1.0.0",
+            "version_normalized": "1.0.0.0",
+        }]
+    }))
+    .unwrap();
+
+    let composer = Composer {
+        require: Some({
+            let mut map = IndexMap::new();
+            map.insert("foo/app".to_owned(), "^1.0".to_owned());
+            map.insert("foo/bar".to_owned(), "^1.0".to_owned());
+            map
+        }),
+        repositories: Some(get_repositories(server.base_url())),
+        ..Default::default()
+    };
+
+    let mut stderr = TestWriter::new();
+    let sorted = |set: HashSet<String>| {
+        let mut list: Vec<String> = set.into_iter().collect();
+        list.sort();
+        list
+    };
+    let packages = ["foo/app".to_owned()];
+    assert_eq!(
+        sorted(composer.update_allowlist(&lock, &packages, UpdateWith::Nothing, &mut stderr)),
+        vec!["foo/app"]
+    );
+    assert_eq!(
+        sorted(composer.update_allowlist(&lock, &packages, UpdateWith::Dependencies, &mut stderr)),
+        vec!["foo/app", "foo/log"]
+    );
+    assert_eq!(
+        sorted(composer.update_allowlist(
+            &lock,
+            &packages,
+            UpdateWith::AllDependencies,
+            &mut stderr
+        )),
+        vec!["foo/app", "foo/bar", "foo/log"]
+    );
+    assert_eq!(
+        sorted(composer.update_allowlist(
+            &lock,
+            &["Foo/*".to_owned(), "foo/none".to_owned()],
+            UpdateWith::Nothing,
+            &mut stderr
+        )),
+        vec!["foo/app", "foo/bar", "foo/log"]
+    );
+    assert_eq!(
+        stderr.output(),
+        "Package \"foo/none\" listed for update is not locked."
+    );
+
+    let mut context = new_context(&composer);
+    for item in lock.packages.iter() {
+        if item.name.as_deref() != Some("foo/app") {
+            context.pin(item.clone());
+        }
+    }
+    let mut stderr = TestWriter::new();
+    let new_lock = composer
+        .get_lock(&mut stderr, Arc::new(Mutex::new(context)))
+        .await
+        .unwrap();
+
+    let versions: Vec<&str> = new_lock
+        .packages
+        .iter()
+        .map(|v| v.version.as_str())
+        .collect();
+    assert_eq!(versions, vec!["1.1.0", "1.0.0", "1.0.0"]);
+    mocks[0].assert();
+    mocks[1].assert_hits(0);
+    mocks[2].assert_hits(0);
+
+    assert_eq!(
+        lock.get_updating_packages(&new_lock),
+        HashSet::from(["foo/app".to_owned()])
+    );
+}
+
+#[tokio::test]
+async fn require_dev() {
+    let server = MockServer::start();
+    for (name, require) in [
+        ("foo/app", json!({"foo/bar": "^1.0"})),
+        ("foo/bar", json!({})),
+        ("foo/test", json!({"foo/bar": "^1.0", "foo/log": "^1.0"})),
+        ("foo/log", json!({})),
+    ] {
+        server.mock(|when, then| {
+            when.method(GET).path(format!("/p2/{}.json", name));
+            then.status(200).json_body(json!({
+                "packages" : {
+                    name : [{
+                        "name" : name,
+                        "version" : "1.0.0",
+                        "version_normalized": "1.0.0.0",
+                        "require": require,
+                    }]
+                }
+            }));
+        });
+    }
+
+    let mut composer = Composer {
+        require: Some({
+            let mut map = IndexMap::new();
+            map.insert("foo/app".to_owned(), "^1.0".to_owned());
+            map
+        }),
+        require_dev: Some({
+            let mut map = IndexMap::new();
+            map.insert("foo/test".to_owned(), "^1.0".to_owned());
+            map
+        }),
+        repositories: Some(get_repositories(server.base_url())),
+        ..Default::default()
+    };
+
+    let mut stderr = TestWriter::new();
+    let lock = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap();
+    let names = |list: &[Version]| {
+        list.iter()
+            .map(|v| v.name.clone().unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names(&lock.packages), vec!["foo/app", "foo/bar"]);
+    assert_eq!(names(&lock.packages_dev), vec!["foo/log", "foo/test"]);
+    assert_eq!(lock.installed(false).count(), 2);
+    assert_eq!(lock.installed(true).count(), 4);
+    assert!(composer.lock_problems(&lock).is_empty());
+
+    // moving a requirement between require and require-dev
+    composer.insert("foo/test", &None, false).unwrap();
+    assert!(composer.require_dev.as_ref().unwrap().is_empty());
+    assert_eq!(composer.require.as_ref().unwrap()["foo/test"], "*");
+    composer.insert("foo/test", &None, true).unwrap();
+    assert!(!composer.require.as_ref().unwrap().contains_key("foo/test"));
+
+    composer
+        .insert("foo/other", &Some("^1.0".to_owned()), true)
+        .unwrap();
+    assert_eq!(
+        composer.lock_problems(&lock),
+        vec!["Required package \"foo/other\" is not present in the lock file."]
+    );
+}
+
+#[test]
+fn autoload_dev() {
+    let composer: Composer = serde_json::from_value(json!({
+        "autoload": {
+            "psr-4": {"App\\": "src/"},
+            "files": ["src/helpers.php"]
+        },
+        "autoload-dev": {
+            "psr-4": {"Tests\\": "tests/"},
+            "files": ["tests/helpers.php"]
+        }
+    }))
+    .unwrap();
+
+    let mut psr4 = Psr4Data::default();
+    psr4.append_json(&composer, false);
+    let (_, dirs) = psr4.to_static();
+    assert!(dirs.contains("'App\\\\'"));
+    assert!(!dirs.contains("Tests"));
+
+    psr4.append_json(&composer, true);
+    let (_, dirs) = psr4.to_static();
+    assert!(dirs.contains("'/tests'"));
+
+    psr4.keep(true);
+    assert_eq!(psr4.to_static().1, "");
+
+    let mut files = FilesData::default();
+    files.append_json(&composer, false);
+    assert!(!files.to_static().contains("tests/helpers.php"));
+    files.append_json(&composer, true);
+    assert!(files.to_static().contains("/tests/helpers.php"));
+}
+
+#[test]
+fn lossless_save() {
+    let contents = r#"{
+  "name": "foo/app",
+  "type": "project",
+  "require": {
+    "php": ">=8.1",
+    "foo/bar": "^1.0",
+    "foo/baz": "^2.0"
+  },
+  "scripts": {
+    "test": "phpunit"
+  },
+  "autoload": {
+    "psr-4": {"App\\": "src/"},
+    "exclude-from-classmap": ["/Tests/"]
+  },
+  "extra": {
+    "laravel": {"dont-discover": []}
+  }
+}
+"#;
+    let mut composer = Composer::from_str(contents).unwrap();
+    assert_eq!(composer.to_json().unwrap(), contents);
+
+    composer.insert("foo/new", &None, false).unwrap();
+    composer.only_remove("foo/bar");
+    composer.set_version("foo/new", "^3.1");
+    composer
+        .insert("foo/test", &Some("^1.0".to_owned()), true)
+        .unwrap();
+    composer.minimum_stability = Some("dev".to_owned());
+    assert_eq!(
+        composer.to_json().unwrap(),
+        r#"{
+  "name": "foo/app",
+  "type": "project",
+  "require": {
+    "php": ">=8.1",
+    "foo/baz": "^2.0",
+    "foo/new": "^3.1"
+  },
+  "scripts": {
+    "test": "phpunit"
+  },
+  "autoload": {
+    "psr-4": {"App\\": "src/"},
+    "exclude-from-classmap": ["/Tests/"]
+  },
+  "extra": {
+    "laravel": {"dont-discover": []}
+  },
+  "require-dev": {
+    "foo/test": "^1.0"
+  },
+  "minimum-stability": "dev"
+}
+"#
+    );
+
+    // the saved text is parsed back to the same struct
+    let saved = Composer::from_str(&composer.to_json().unwrap()).unwrap();
+    assert_eq!(
+        serde_json::to_value(&saved).unwrap(),
+        serde_json::to_value(&composer).unwrap()
+    );
+
+    let mut composer = Composer::default();
+    composer.insert("foo/bar", &None, false).unwrap();
+    assert_eq!(
+        composer.to_json().unwrap(),
+        "{\n    \"require\": {\n        \"foo/bar\": \"*\"\n    }\n}\n"
+    );
+}
+
+#[test]
+fn content_hash() {
+    let composer = Composer::from_str("{}").unwrap();
+    assert_eq!(
+        composer.content_hash().unwrap(),
+        "d751713988987e9331980363e24189ce"
+    );
+
+    let contents = r#"{
+    "name": "foo/app",
+    "require": {"php": ">=8.1", "foo/bar": "^1.0"},
+    "scripts": {"test": "phpunit"},
+    "extra": {"branch-alias": {}, "title": "café"},
+    "config": {"platform": {"php": "8.1.0"}, "sort-packages": true}
+}"#;
+    let mut composer = Composer::from_str(contents).unwrap();
+
+    let mut json = String::new();
+    php_json(
+        &json!({
+            "config": {"platform": {"php": "8.1.0"}},
+            "extra": {"branch-alias": {}, "title": "café"},
+            "name": "foo/app",
+            "require": {"php": ">=8.1", "foo/bar": "^1.0"},
+        }),
+        &mut json,
+    );
+    assert_eq!(
+        json,
+        r#"{"config":{"platform":{"php":"8.1.0"}},"extra":{"branch-alias":[],"title":"caf\u00e9"},"name":"foo\/app","require":{"php":">=8.1","foo\/bar":"^1.0"}}"#
+    );
+
+    let hash = composer.content_hash().unwrap();
+    {
+        use md5::{Digest, Md5};
+        assert_eq!(hash, hex::encode(Md5::digest(json.as_bytes())));
+    }
+
+    // scripts are not part of the hash, requirements are
+    let scripts = contents.replace("phpunit", "pest");
+    assert_eq!(
+        Composer::from_str(&scripts)
+            .unwrap()
+            .content_hash()
+            .unwrap(),
+        hash
+    );
+    composer.insert("foo/baz", &None, false).unwrap();
+    assert_ne!(composer.content_hash().unwrap(), hash);
+}
+
+#[test]
+fn composer_lock_schema() {
+    // written by composer: empty maps are lists, and there is no version_normalized
+    let lock = ComposerLock::from_str(
+        r#"{
+    "_readme": ["This file is @generated automatically"],
+    "content-hash": "d751713988987e9331980363e24189ce",
+    "packages": [{"name": "foo/bar", "version": "v1.2.3", "type": "library"}],
+    "packages-dev": [{"name": "foo/test", "version": "dev-main"}],
+    "aliases": [],
+    "minimum-stability": "stable",
+    "stability-flags": [],
+    "prefer-stable": false,
+    "prefer-lowest": false,
+    "platform": [],
+    "platform-dev": {"ext-json": "*"},
+    "plugin-api-version": "2.6.0"
+}
+"#,
+    )
+    .unwrap();
+    assert_eq!(lock.packages[0].version_normalized, "1.2.3.0");
+    assert_eq!(lock.packages_dev[0].version_normalized, "dev-main");
+    assert!(lock.stability_flags.is_empty() && lock.platform.is_empty());
+    assert_eq!(lock.platform_dev["ext-json"], "*");
+
+    let keys: Vec<String> = serde_json::from_str::<Value>(&lock.json().unwrap())
+        .unwrap()
+        .as_object()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect();
+    assert_eq!(
+        keys,
+        vec![
+            "_readme",
+            "content-hash",
+            "packages",
+            "packages-dev",
+            "aliases",
+            "minimum-stability",
+            "stability-flags",
+            "prefer-stable",
+            "prefer-lowest",
+            "platform",
+            "platform-dev",
+            "plugin-api-version"
+        ]
+    );
+    assert!(lock
+        .json()
+        .unwrap()
+        .starts_with("{\n    \"_readme\": [\n        \"This"));
+    assert!(lock.json().unwrap().ends_with("}\n"));
+}
+
+#[tokio::test]
+async fn lock_platform() {
+    let server = MockServer::start();
+    beta_server(&server);
+
+    let composer = Composer::from_str(
+        r#"{
+    "require": {"php": ">=7.0", "foo/bar": "^1.0"},
+    "require-dev": {"ext-json": "*"}
+}"#,
+    )
+    .unwrap();
+    let mut ctx = new_context(&composer);
+    ctx.repositories = vec![RepositoryConfig::new("composer", &server.base_url())];
+    ctx.php_extensions.push("json".to_owned());
+
+    let mut stderr = TestWriter::new();
+    let lock = composer
+        .get_lock(&mut stderr, Arc::new(Mutex::new(ctx)))
+        .await
+        .unwrap();
+    assert_eq!(lock.content_hash, composer.content_hash().unwrap());
+    assert_eq!(lock.platform["php"], ">=7.0");
+    assert_eq!(lock.platform_dev["ext-json"], "*");
+}
+
+#[test]
+fn repositories_config() {
+    let composer = Composer::from_str(
+        r#"{
+    "repositories": [
+        {"type": "composer", "url": "https://a.org", "canonical": false, "only": ["foo/*"]},
+        {"type": "composer", "url": "https://b.org", "exclude": ["foo/bar"]},
+        {"packagist.org": false}
+    ]
+}"#,
+    )
+    .unwrap();
+    let repositories = composer.repositories.as_ref().unwrap();
+    assert!(repositories.packagist_disabled());
+    let list: Vec<&RepositoryConfig> = repositories.configs().collect();
+    assert_eq!(list.len(), 2);
+    assert!(!list[0].canonical);
+    assert_eq!(list[0].only, vec!["foo/*".to_owned()]);
+    assert!(list[1].canonical);
+    assert_eq!(list[1].exclude, vec!["foo/bar".to_owned()]);
+
+    // written back as it was
+    let value = serde_json::to_value(repositories).unwrap();
+    assert_eq!(
+        value,
+        json!([
+            {"type": "composer", "url": "https://a.org", "canonical": false, "only": ["foo/*"]},
+            {"type": "composer", "url": "https://b.org", "exclude": ["foo/bar"]},
+            {"packagist.org": false}
+        ])
+    );
+
+    // the object form, with a packagist mirror
+    let mut composer = Composer::from_str(
+        r#"{
+    "repositories": {
+        "private": {"type": "composer", "url": "https://a.org"}
+    }
+}"#,
+    )
+    .unwrap();
+    composer
+        .set(
+            false,
+            "repo.packagist",
+            &Some("composer".to_owned()),
+            &Some("https://mirror.org".to_owned()),
+        )
+        .unwrap();
+    let value = serde_json::to_value(composer.repositories.as_ref().unwrap()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "private": {"type": "composer", "url": "https://a.org"},
+            "packagist": {"type": "composer", "url": "https://mirror.org"}
+        })
+    );
+}
+
+/// foo/bar 1.0.0 in the first repository, foo/bar 1.0.0 and 2.0.0 in the second
+async fn priority(first: serde_json::Value) -> (ComposerLock, usize) {
+    let private = MockServer::start();
+    let public = MockServer::start();
+    private.mock(|when, then| {
+        when.method(GET).path("/p2/foo/bar.json");
+        then.status(200).json_body(json!({
+            "packages" : {
+                "foo/bar" : [{
+                    "version" : "1.0.0",
+                    "version_normalized": "1.0.0.0",
+                    "dist": {"url": "https://private.org/bar.zip", "type": "zip", "reference": "a"},
+                }]
+            }
+        }));
+    });
+    let public_mock = public.mock(|when, then| {
+        when.method(GET).path("/p2/foo/bar.json");
+        then.status(200).json_body(json!({
+            "packages" : {
+                "foo/bar" : [{
+                    "version" : "2.0.0",
+                    "version_normalized": "2.0.0.0",
+                }, {
+                    "version" : "1.0.0",
+                    "version_normalized": "1.0.0.0",
+                    "dist": {"url": "https://public.org/bar.zip", "type": "zip", "reference": "b"},
+                }]
+            }
+        }));
+    });
+
+    let mut first = first;
+    first["type"] = json!("composer");
+    first["url"] = json!(private.base_url());
+    let composer = Composer::from_str(
+        &json!({
+            "require": {"foo/bar": "*"},
+            "repositories": [
+                first,
+                {"type": "composer", "url": public.base_url()},
+                {"packagist.org": false}
+            ]
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    let mut stderr = TestWriter::new();
+    let lock = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap();
+    (lock, public_mock.hits())
+}
+
+#[tokio::test]
+async fn repository_priority() {
+    // canonical, the second repository is not asked
+    let (lock, hits) = priority(json!({})).await;
+    assert_eq!(lock.packages[0].version, "1.0.0");
+    assert_eq!(hits, 0);
+
+    // not canonical, the versions of the second repository are added
+    let (lock, hits) = priority(json!({"canonical": false})).await;
+    assert_eq!(lock.packages[0].version, "2.0.0");
+    assert_eq!(hits, 1);
+
+    // the first repository is not asked for foo/bar
+    let (lock, _) = priority(json!({"only": ["other/*"]})).await;
+    assert_eq!(lock.packages[0].version, "2.0.0");
+    let (lock, _) = priority(json!({"exclude": ["foo/*"]})).await;
+    assert_eq!(lock.packages[0].version, "2.0.0");
+}
+
+#[tokio::test]
+async fn path_repository() {
+    let root = tempfile::tempdir().unwrap();
+    for (dir, json) in [
+        (
+            "foo",
+            json!({"name": "acme/foo", "version": "1.2.0", "require": {"acme/bar": "^2.0"}}),
+        ),
+        ("bar", json!({"name": "acme/bar", "version": "2.1.0"})),
+    ] {
+        let dir = root.path().join("packages").join(dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("composer.json"), json.to_string()).unwrap();
+    }
+
+    let composer = Composer::from_str(
+        &json!({
+            "require": {"acme/foo": "^1.0"},
+            "repositories": [
+                {
+                    "type": "path",
+                    "url": format!("{}/packages/*", root.path().display()),
+                    "options": {"symlink": false}
+                },
+                {"packagist.org": false}
+            ]
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    let mut stderr = TestWriter::new();
+    let lock = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap();
+    assert_eq!(lock.packages.len(), 2);
+    let bar = lock.find_version("acme/bar").unwrap();
+    assert_eq!(bar.version, "2.1.0");
+    let dist = bar.dist.as_ref().unwrap();
+    assert_eq!(dist._type, "path");
+    assert_eq!(dist.url, format!("{}/packages/bar", root.path().display()));
+    assert_eq!(bar.transport_options, Some(json!({"symlink": false})));
+}
+
+#[tokio::test]
+async fn vcs_repository() {
+    use crate::{download::dist_path, repository::vcs::tests::bare_repo};
+
+    let root = tempfile::tempdir().unwrap();
+    let (bare, refs) = bare_repo(
+        root.path(),
+        &[
+            (json!({"name": "acme/foo"}), "1.0.0"),
+            (
+                json!({"name": "acme/foo", "require": {"php": ">=7.0"}}),
+                "1.1.0",
+            ),
+        ],
+    );
+
+    let composer = Composer::from_str(
+        &json!({
+            "require": {"acme/foo": "^1.0"},
+            "repositories": [
+                {"type": "git", "url": bare.to_string_lossy()},
+                {"packagist.org": false}
+            ]
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    let context = new_context(&composer);
+    let cache_dir = context.cache_dir.clone();
+    let mut stderr = TestWriter::new();
+    let lock = composer
+        .get_lock(&mut stderr, Arc::new(Mutex::new(context)))
+        .await
+        .unwrap();
+    let foo = lock.find_version("acme/foo").unwrap();
+    assert_eq!(foo.version, "1.1.0");
+    assert_eq!(foo.source.as_ref().unwrap().reference, refs[1]);
+    assert!(foo.dist.is_none());
+
+    // archived from the mirror in the cache
+    let files_dir = cache_dir.join("files");
+    Downloader::new(Http::insecure(), 1)
+        .download(lock.packages.iter(), &files_dir, &mut stderr)
+        .await
+        .unwrap();
+    assert!(dist_path(&files_dir, foo).exists());
+}
+
+#[tokio::test]
+async fn private_vcs_repository() {
+    use crate::{auth::Auth, download::dist_path, repository::vcs::tests::bare_repo};
+
+    // served with the dumb http protocol of git, only with the credentials
+    let root = tempfile::tempdir().unwrap();
+    let (bare, refs) = bare_repo(root.path(), &[(json!({"name": "acme/foo"}), "1.0.0")]);
+    let status = std::process::Command::new("git")
+        .arg("update-server-info")
+        .current_dir(&bare)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let server = MockServer::start();
+    let mut dirs = vec![bare.clone()];
+    while let Some(dir) = dirs.pop() {
+        for path in std::fs::read_dir(dir).unwrap().flatten().map(|e| e.path()) {
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let file = path.strip_prefix(&bare).unwrap().to_string_lossy();
+            let body = std::fs::read(&path).unwrap();
+            server.mock(|when, then| {
+                when.method(GET)
+                    .path(format!("/repo.git/{}", file))
+                    .header("authorization", "Basic dXNlcjpzZWNyZXQ=");
+                then.status(200).body(body);
+            });
+        }
+    }
+
+    let host = format!("{}:{}", server.host(), server.port());
+    let composer = Composer::from_str(
+        &json!({
+            "require": {"acme/foo": "^1.0"},
+            "repositories": [
+                {"type": "vcs", "url": format!("http://user:secret@{}/repo.git", host)},
+                {"packagist.org": false}
+            ]
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let auth: Arc<Auth> = Arc::new(
+        serde_json::from_value(
+            json!({"http-basic": {host.clone(): {"username": "user", "password": "secret"}}}),
+        )
+        .unwrap(),
+    );
+    let mut context = new_context(&composer);
+    context.http = Http::insecure().auth(auth.clone());
+    let mut stderr = TestWriter::new();
+    let lock = composer
+        .get_lock(&mut stderr, Arc::new(Mutex::new(context)))
+        .await
+        .unwrap();
+
+    // the password is not written into the lock
+    let json = lock.json().unwrap();
+    assert!(!json.contains("secret"));
+    assert!(json.contains(&format!("\"http://{}/repo.git\"", host)));
+
+    // installed from the lock, into an empty cache, with the credentials of auth.json
+    let lock = ComposerLock::from_str(&json).unwrap();
+    let foo = lock.find_version("acme/foo").unwrap();
+    assert_eq!(foo.source.as_ref().unwrap().reference, refs[0]);
+    let cache_dir = tempfile::tempdir().unwrap();
+    let files_dir = cache_dir.path().join("files");
+    let result = Downloader::new(Http::insecure(), 1)
+        .download(lock.packages.iter(), &files_dir, &mut stderr)
+        .await;
+    assert!(matches!(result, Err(ComposerError::Git(_))));
+    let downloader = Downloader::new(Http::insecure().auth(auth), 1);
+    downloader
+        .download(lock.packages.iter(), &files_dir, &mut stderr)
+        .await
+        .unwrap();
+    assert!(dist_path(&files_dir, foo).exists());
+
+    let source = foo.source.as_ref().unwrap();
+    let target = cache_dir.path().join("vendor/acme/foo");
+    downloader
+        .checkout(&source.url, &source.reference, &target)
+        .unwrap();
+    assert!(target.join("src/Foo.php").exists());
+    assert!(!stderr.output().contains("secret"));
+}
+
+#[tokio::test]
+async fn artifact_repository() {
+    use crate::{
+        download::dist_path,
+        repository::artifact::{mark_local, tests::archive},
+    };
+
+    let root = tempfile::tempdir().unwrap();
+    for (file, version) in [("foo-1.0.0.zip", "1.0.0"), ("foo-1.1.0.zip", "1.1.0")] {
+        std::fs::write(
+            root.path().join(file),
+            archive("", json!({"name": "acme/foo", "version": version})),
+        )
+        .unwrap();
+    }
+
+    let composer = Composer::from_str(
+        &json!({
+            "require": {"acme/foo": "^1.0"},
+            "repositories": [
+                {"type": "artifact", "url": root.path().to_string_lossy()},
+                {"packagist.org": false}
+            ]
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    let mut stderr = TestWriter::new();
+    let lock = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap();
+    let foo = lock.find_version("acme/foo").unwrap();
+    assert_eq!(foo.version, "1.1.0");
+
+    // read where it is, nothing is copied into the cache
+    let files_dir = tempfile::tempdir().unwrap();
+    let hashes = Downloader::new(Http::insecure(), 1)
+        .download(lock.packages.iter(), files_dir.path(), &mut stderr)
+        .await
+        .unwrap();
+    let file = root.path().join("foo-1.1.0.zip");
+    assert_eq!(dist_path(files_dir.path(), foo), file);
+    assert_eq!(
+        hashes["acme/foo"],
+        crate::download::integrity(&std::fs::read(&file).unwrap())
+    );
+    assert!(std::fs::read_dir(files_dir.path())
+        .unwrap()
+        .next()
+        .is_none());
+
+    // local again when read from composer.lock, for the artifact repositories only
+    let json = lock.json().unwrap();
+    let mut locked = ComposerLock::from_str(&json).unwrap();
+    assert!(!locked.packages[0].dist.as_ref().unwrap().local);
+    mark_local(locked.packages.iter_mut(), &[root.path().to_path_buf()]);
+    assert_eq!(dist_path(files_dir.path(), &locked.packages[0]), file);
+
+    // an archive elsewhere on the disk is never read, whatever its url
+    let other = tempfile::tempdir().unwrap();
+    std::fs::copy(&file, other.path().join("foo.zip")).unwrap();
+    let mut locked = ComposerLock::from_str(&json.replace(
+        &*root.path().to_string_lossy(),
+        &other.path().to_string_lossy(),
+    ))
+    .unwrap();
+    mark_local(locked.packages.iter_mut(), &[root.path().to_path_buf()]);
+    assert!(!locked.packages[0].dist.as_ref().unwrap().local);
+    let result = Downloader::new(Http::insecure(), 1)
+        .download(locked.packages.iter(), files_dir.path(), &mut stderr)
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn package_repository() {
+    use crate::{download::dist_path, repository::artifact::tests::archive};
+
+    let server = MockServer::start();
+    let zip = server.mock(|when, then| {
+        when.method(GET).path("/lib-1.0.0.zip");
+        then.status(200)
+            .body(archive("lib/", json!({"name": "legacy/lib"})));
+    });
+
+    let composer = Composer::from_str(
+        &json!({
+            "require": {"legacy/lib": "^1.0"},
+            "repositories": [
+                {
+                    "type": "package",
+                    "package": {
+                        "name": "legacy/lib",
+                        "version": "1.0.0",
+                        "dist": {"url": server.url("/lib-1.0.0.zip"), "type": "zip"},
+                        "autoload": {"classmap": ["src/"]}
+                    }
+                },
+                {"packagist.org": false}
+            ]
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    let mut stderr = TestWriter::new();
+    let lock = composer
+        .get_lock(&mut stderr, default_context(&composer))
+        .await
+        .unwrap();
+    let lib = lock.find_version("legacy/lib").unwrap();
+    assert_eq!(lib.version, "1.0.0");
+    assert!(lib.autoload.is_some());
+
+    let files_dir = tempfile::tempdir().unwrap();
+    Downloader::new(Http::insecure(), 1)
+        .download(lock.packages.iter(), files_dir.path(), &mut stderr)
+        .await
+        .unwrap();
+    zip.assert_hits(1);
+    assert!(dist_path(files_dir.path(), lib).exists());
+}
+
+#[tokio::test]
+async fn auth() {
+    use crate::{auth::Auth, repository::artifact::tests::archive};
+
+    let server = MockServer::start();
+    let host = format!("{}:{}", server.host(), server.port());
+    let metadata = server.mock(|when, then| {
+        when.method(GET)
+            .path("/p2/acme/foo.json")
+            .header("authorization", "Bearer secret-token");
+        then.status(200).json_body(json!({
+            "packages": {"acme/foo": [{
+                "version": "1.0.0",
+                "version_normalized": "1.0.0.0",
+                "dist": {
+                    "url": format!("http://user:secret-password@{}/foo.zip", host),
+                    "type": "zip",
+                    "reference": "1.0.0"
+                }
+            }]}
+        }));
+    });
+    let zip = server.mock(|when, then| {
+        when.method(GET)
+            .path("/foo.zip")
+            .header("authorization", "Bearer secret-token");
+        then.status(200)
+            .body(archive("foo/", json!({"name": "acme/foo"})));
+    });
+
+    let composer = Composer::from_str(
+        &json!({
+            "require": {"acme/foo": "^1.0"},
+            "repositories": [
+                {"type": "composer", "url": server.base_url()},
+                {"packagist.org": false}
+            ]
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let auth: Arc<Auth> = Arc::new(
+        serde_json::from_value(json!({"bearer": {host.clone(): "secret-token"}})).unwrap(),
+    );
+    let mut context = new_context(&composer);
+    context.http = Http::insecure().auth(auth.clone());
+
+    let mut stderr = TestWriter::new();
+    let lock = composer
+        .get_lock(&mut stderr, Arc::new(Mutex::new(context)))
+        .await
+        .unwrap();
+    metadata.assert_hits(1);
+
+    // the credentials of the dist url are not written into the lock
+    let json = lock.json().unwrap();
+    assert!(!json.contains("secret"));
+    assert!(json.contains(&format!("\"http://{}/foo.zip\"", host)));
+
+    // installed from the lock with the credentials of auth.json
+    let lock = ComposerLock::from_str(&json).unwrap();
+    let files_dir = tempfile::tempdir().unwrap();
+    Downloader::new(Http::insecure().auth(auth), 1)
+        .download(lock.packages.iter(), files_dir.path(), &mut stderr)
+        .await
+        .unwrap();
+    zip.assert_hits(1);
+    assert!(!stderr.output().contains("secret"));
+    // a repository needing http-basic reports the credentials, not a missing package
+    let server = MockServer::start();
+    let basic = server.mock(|when, then| {
+        when.method(GET)
+            .path("/p2/acme/bar.json")
+            .header("authorization", "Basic dXNlcjpzZWNyZXQ=");
+        then.status(200).json_body(json!({
+            "packages": {"acme/bar": [{"version": "1.0.0", "version_normalized": "1.0.0.0"}]}
+        }));
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/p2/acme/bar.json");
+        then.status(401);
+    });
+    let composer = Composer::from_str(
+        &json!({
+            "require": {"acme/bar": "^1.0"},
+            "repositories": [
+                {"type": "composer", "url": server.base_url()},
+                {"packagist.org": false}
+            ]
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let host = format!("{}:{}", server.host(), server.port());
+    for (credentials, authorized) in [
+        (json!({}), false),
+        (
+            json!({host.clone(): {"username": "user", "password": "wrong"}}),
+            false,
+        ),
+        (
+            json!({host: {"username": "user", "password": "secret"}}),
+            true,
+        ),
+    ] {
+        let auth: Auth = serde_json::from_value(json!({ "http-basic": credentials })).unwrap();
+        let mut context = new_context(&composer);
+        context.http = Http::insecure().auth(Arc::new(auth));
+
+        let result = composer
+            .get_lock(&mut TestWriter::new(), Arc::new(Mutex::new(context)))
+            .await;
+        if authorized {
+            assert_eq!(result.unwrap().packages[0].version, "1.0.0");
+        } else {
+            assert!(matches!(
+                result,
+                Err(ComposerError::Unauthorized(url)) if url == server.url("/p2/acme/bar.json")
+            ));
+        }
+    }
+    basic.assert_hits(1);
 }
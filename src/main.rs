@@ -1,50 +1,106 @@
+use std::path::PathBuf;
+
+use build::BuildConfig;
 use clap::{Parser, Subcommand};
 use config::GlobalConfig;
 use error::ComposerError;
 use io::StderrWriter;
-use json::Composer;
+use json::{Composer, UpdateWith};
 use package::P2;
 
+mod auth;
 mod autoload;
+mod build;
 mod config;
+mod constraint;
+mod download;
 mod error;
+mod http;
 mod io;
 mod json;
 mod package;
+mod repository;
+mod resolver;
 mod search;
 
 #[tokio::main]
-async fn main() -> Result<(), ComposerError> {
-    let cli = Cli::parse();
+async fn main() -> std::process::ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::ExitCode::FAILURE
+        }
+    }
+}
 
-    let mut composer = Composer::new()?;
+async fn run(cli: Cli) -> Result<(), ComposerError> {
     let mut std_err = StderrWriter {};
 
+    // it has a config of its own, and runs outside of a project
+    if let Commands::BuildRepo { config, output_dir } = &cli.command {
+        let config = BuildConfig::from_file(config)?;
+        let output = config.output_dir(output_dir.as_deref());
+        config
+            .build(
+                &output,
+                &package::cache_dir()?,
+                config.http()?,
+                &mut std_err,
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let mut composer = Composer::new()?;
+
     //println!("{:?}", composer);
 
     match &cli.command {
-        Commands::Require { name, version } => {
-            composer.insert(name, version)?;
+        Commands::Require { name, version, dev } => {
+            composer.insert(name, version, *dev)?;
             composer.save()?;
 
-            composer.install(name, &mut std_err).await?;
+            composer.install(name, true, &mut std_err).await?;
+        }
+        Commands::Install { no_dev } => {
+            composer.install_from_lock(!no_dev, &mut std_err).await?;
         }
-        Commands::Install => {
-            composer.install("", &mut std_err).await?;
+        Commands::Update {
+            packages,
+            with_dependencies,
+            with_all_dependencies,
+            lock,
+            no_dev,
+        } => {
+            let with = if *with_all_dependencies {
+                UpdateWith::AllDependencies
+            } else if *with_dependencies {
+                UpdateWith::Dependencies
+            } else {
+                UpdateWith::Nothing
+            };
+            composer
+                .update(packages, with, *lock, !no_dev, &mut std_err)
+                .await?;
         }
-        Commands::Clear => {
-            P2::clear().expect("clear dir failed");
+        Commands::Clear { files, repo, vcs } => {
+            // without a flag, every cache is cleared
+            let all = !files && !repo && !vcs;
+            P2::clear(*files || all, *repo || all, *vcs || all).expect("clear dir failed");
         }
-        Commands::Remove { name } => {
-            composer.remove(name, &mut std_err).await?;
+        Commands::Remove { name, dev } => {
+            composer.remove(name, *dev, &mut std_err).await?;
             composer.save()?;
         }
-        Commands::DumpAutoload => {
-            composer.dump_autoload()?;
+        Commands::DumpAutoload { no_dev } => {
+            composer.dump_autoload(!no_dev)?;
         }
         Commands::Search { keyword } => {
-            search::Search::new(keyword).search().await?;
+            let repo = composer.repository().await?;
+            search::Search::new(keyword).search(&repo).await?;
         }
+        Commands::BuildRepo { .. } => unreachable!("built before reading composer.json"),
         Commands::Config {
             global,
             unset,
@@ -79,22 +135,84 @@ enum Commands {
     Require {
         name: String,
         version: Option<String>,
+
+        /// Add requirement to require-dev
+        #[arg(long)]
+        dev: bool,
     },
 
     /// Installs the project dependencies from the composer.lock file if present, or falls back on the composer.json
-    Install,
+    Install {
+        /// Disables installation of require-dev packages
+        #[arg(long)]
+        no_dev: bool,
+    },
+
+    /// Updates your dependencies to the latest version according to composer.json, and updates the composer.lock file
+    Update {
+        /// Packages that should be updated, if not provided all packages are
+        packages: Vec<String>,
+
+        /// Update also dependencies of packages in the argument list, except those which are root requirements
+        #[arg(short = 'w', long)]
+        with_dependencies: bool,
+
+        /// Update also dependencies of packages in the argument list, including those which are root requirements
+        #[arg(short = 'W', long)]
+        with_all_dependencies: bool,
+
+        /// Only updates the lock file hash to suppress warning about the lock file being out of date
+        #[arg(long)]
+        lock: bool,
+
+        /// Disables installation of require-dev packages
+        #[arg(long)]
+        no_dev: bool,
+    },
 
     /// Clears composer's internal package cache
-    Clear,
+    Clear {
+        /// Only clear the cache of downloaded archives
+        #[arg(long)]
+        files: bool,
+
+        /// Only clear the cache of repository metadata
+        #[arg(long)]
+        repo: bool,
+
+        /// Only clear the mirrors of git repositories
+        #[arg(long)]
+        vcs: bool,
+    },
 
     /// Removes a package from the require or require-dev
-    Remove { name: String },
+    Remove {
+        name: String,
+
+        /// Removes a package from the require-dev section
+        #[arg(long)]
+        dev: bool,
+    },
 
     /// Dumps the autoloader
-    DumpAutoload,
+    DumpAutoload {
+        /// Skips the autoload-dev rules
+        #[arg(long)]
+        no_dev: bool,
+    },
 
     /// Searches for packages
     Search { keyword: String },
+
+    /// Builds a static composer repository of the packages listed in a config file
+    BuildRepo {
+        /// The config file, with the homepage, repositories, require and lock to mirror
+        config: PathBuf,
+
+        /// The directory to write the repository to, the output-dir of the config by default
+        output_dir: Option<PathBuf>,
+    },
+
     /// Sets config options
     Config {
         /// setting global
@@ -1,343 +1,261 @@
 //! disponse P2 and parse composer.lock file
 
 use std::{
-    collections::{HashMap, HashSet},
-    fs::{create_dir_all, read_to_string, File},
-    future::Future,
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{create_dir_all, read_to_string, remove_dir_all, File},
     io::Write,
     path::{Path, PathBuf},
-    pin::Pin,
     process::Command,
     sync::{Arc, Mutex},
     time::Duration,
 };
 
 use dirs::home_dir;
-use reqwest::header::USER_AGENT;
-use semver::{Comparator, Prerelease, VersionReq};
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use tokio::time::sleep;
+use serde_json::Value;
+use tokio::task::JoinSet;
 
 use crate::{
+    auth::serialize_redacted,
     autoload::{FilesData, Psr4Data, StaticData},
+    config::RepositoryConfig,
+    constraint::{normalize, Constraint, Stability},
+    download::{dist_path, git_source, is_checkout, Downloader},
     error::ComposerError,
+    http::Http,
+    io::ErrWriter,
+    repository::{path, RepositorySet},
 };
 
 const CACHE_DIR: &str = ".cache/phpp";
 pub const MY_USER_AGENT: &str = "tu6ge/phpp";
+/// the number of package metadata files fetched at the same time
+const MAX_FETCHES: usize = 16;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct P2 {
-    pub(crate) packages: HashMap<String, Vec<Version>>,
+    pub(crate) packages: HashMap<String, Vec<serde_json::Map<String, Value>>>,
+    /// `composer/2.0` when every version only lists the keys changed from the previous one
+    #[serde(default)]
+    minified: Option<String>,
 }
 
 impl P2 {
-    pub fn down_all(
-        name: String,
-        version: Option<String>,
+    /// load the metadata of the `requirements`, and of every package required by
+    /// their matching versions, into `Context::pool`
+    ///
+    /// At most `MAX_FETCHES` packages are fetched at the same time, on the client
+    /// of the context, and every package is fetched only once.
+    pub async fn down_all(
+        requirements: Vec<(String, Option<String>)>,
         ctx: Arc<Mutex<Context>>,
-    ) -> Pin<Box<dyn Future<Output = Result<(), ComposerError>> + Send>> {
-        Box::pin(async move {
-            {
-                if ctx.lock().unwrap().hash_set.contains(&name) {
-                    return Ok(());
-                }
+    ) -> Result<(), ComposerError> {
+        let (repo, configs, cache_dir, http, ttl, minimum, flags) = {
+            let ctx = ctx.lock().unwrap();
+            (
+                ctx.repository.clone(),
+                ctx.repositories.clone(),
+                ctx.cache_dir.clone(),
+                ctx.http.clone(),
+                ctx.metadata_ttl,
+                ctx.minimum_stability,
+                ctx.stability_flags.clone(),
+            )
+        };
+        let repo = match repo {
+            Some(repo) => repo,
+            None => {
+                let repo = Arc::new(RepositorySet::new(&configs, http, cache_dir, ttl).await?);
+                ctx.lock().unwrap().repository = Some(repo.clone());
+                repo
             }
+        };
 
-            let json = {
-                let url = ctx.lock().unwrap().p2_url.clone();
-
-                let exists = Self::file_exists(&name, &url)?;
-                if exists {
-                    Self::read_file(&name, &url)?
-                } else {
-                    sleep(Duration::from_millis(200)).await;
-
-                    let json = match Self::down(&name, &url).await {
-                        Ok(json) => json,
-                        Err(ComposerError::NotFoundPackage(_)) => return Ok(()),
-                        Err(e) => return Err(e),
-                    };
+        let mut pending: VecDeque<(String, Option<String>)> = requirements.into();
+        // the requirements on packages which are being fetched
+        let mut waiting: HashMap<String, Vec<Option<String>>> = HashMap::new();
+        let mut tasks = JoinSet::new();
 
-                    Self::save(&name, &json, &url)?;
-                    json
+        loop {
+            while let Some((name, version)) = pending.pop_front() {
+                if let Some(list) = waiting.get_mut(&name) {
+                    list.push(version);
+                    continue;
                 }
-            };
-
-            #[allow(clippy::expect_fun_call)]
-            let tree: P2 = serde_json::from_str(&json)
-                .expect(&format!("parse json failed, package: {}", name));
-
-            let version_list = tree.packages.get(&name).expect("no found package name");
-            //.ok_or(ComposerError::NotFoundPackageName(name.to_owned()))?;
-
-            let mut info = version_list[0].clone();
-            if let Some(req) = version {
-                for item in version_list.iter() {
-                    if Self::semver_check(&name, &req, &item.version)? {
-                        info = item.clone();
-                        break;
-                    }
+                let mut ctx = ctx.lock().unwrap();
+                if ctx.pool.contains_key(&name) {
+                    pending.extend(ctx.expand(&name, version.as_deref()));
+                    continue;
                 }
-            } else {
-                // find last stable version
-                for item in version_list.iter() {
-                    let version = item.semver()?;
-                    if version.pre == Prerelease::EMPTY {
-                        info = item.clone();
-                        break;
-                    }
+                if tasks.len() >= MAX_FETCHES {
+                    pending.push_front((name, version));
+                    break;
                 }
-                ctx.lock().unwrap().first_package = Some(info.clone());
+                waiting.insert(name.clone(), vec![version]);
+                let dev = *flags.get(&name).unwrap_or(&minimum) == Stability::Dev;
+                let repo = repo.clone();
+                tasks.spawn(async move {
+                    let list = repo.versions(&name, dev).await?;
+                    Ok::<_, ComposerError>((name, list))
+                });
             }
-            info.name = Some(name.to_owned());
 
-            {
-                ctx.lock().unwrap().versions.push(info.clone());
-                ctx.lock().unwrap().hash_set.insert(name.to_owned());
-            }
+            let (name, list) = match tasks.join_next().await {
+                Some(res) => res??,
+                None => return Ok(()),
+            };
 
-            println!("  - Locking {}({})", name, info.version);
-            let deps = &info.require;
-            if let Some(Require::Map(deps)) = deps {
-                for (dep_name, version) in deps.iter() {
-                    //println!("source: {}, deps: {}, version:{}", name, dep_name, version);
-                    if dep_name == "php" {
-                        let mut ctx = ctx.lock().unwrap();
-                        let php_version = &ctx.php_version;
-
-                        if !Self::semver_check(&name, version, php_version)? {
-                            ctx.php_version_error
-                                .push((format!("{}({})", name, info.version), version.to_owned()));
-                        }
-                    } else if matches!(dep_name.find("ext-"), Some(0)) {
-                        let ext = dep_name.replace("ext-", "");
-                        let mut ctx = ctx.lock().unwrap();
-                        let exists = ctx.exists_extension(&ext);
-                        if !exists {
-                            ctx.php_extensions_error
-                                .push((format!("{}({})", name, info.version), ext.to_owned()));
-                        }
-                    } else {
-                        P2::down_all(dep_name.to_owned(), Some(version.to_owned()), ctx.clone())
-                            .await?;
-                    }
-                }
+            let mut ctx = ctx.lock().unwrap();
+            let list = with_aliases(&name, list, &ctx.aliases);
+            ctx.pool.insert(name.clone(), list);
+            for version in waiting.remove(&name).unwrap_or_default() {
+                pending.extend(ctx.expand(&name, version.as_deref()));
             }
-
-            Ok(())
-        })
+        }
     }
 
-    pub async fn down(name: &str, p2_url: &str) -> Result<String, ComposerError> {
-        let mut url = String::from(p2_url);
-        url.push_str(name);
-        url.push_str(".json");
-
-        let response = reqwest::Client::new()
-            .get(url)
-            .header(USER_AGENT, MY_USER_AGENT)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            return Err(ComposerError::NotFoundPackage(name.to_owned()));
+    /// the versions of `name` in a p2 file, expanded when it is minified
+    pub(crate) fn parse(name: &str, json: &str) -> Result<Vec<Version>, ComposerError> {
+        let mut tree: P2 = serde_json::from_str(json)?;
+        let list = tree.packages.remove(name).unwrap_or_default();
+        if tree.minified.as_deref() == Some("composer/2.0") {
+            Self::versions(expand(list))
+        } else {
+            Self::versions(list)
         }
-
-        let json = response.text().await?;
-
-        Ok(json)
     }
 
-    pub fn file_exists(name: &str, p2_url: &str) -> Result<bool, ComposerError> {
-        let cache_dir = home_dir()
-            .ok_or(ComposerError::NotFoundHomeDir)?
-            .join(CACHE_DIR);
-        let repo_dir = cache_dir.join("repo");
-        let p2_url = String::from(p2_url).replace(":", "-");
-        let p2_url = p2_url.replace("/", "-");
-        let repo_dir = repo_dir.join(p2_url);
-        create_dir_all(&repo_dir)?;
-
-        let name_dir = name.replace('/', "-");
-        let filename = format!("provider-{}.json", name_dir);
-        let final_path = repo_dir.join(filename);
-
-        Ok(final_path.exists())
+    pub(crate) fn versions(
+        list: Vec<serde_json::Map<String, Value>>,
+    ) -> Result<Vec<Version>, ComposerError> {
+        let mut versions = Vec::new();
+        for item in list {
+            versions.push(serde_json::from_value(Value::Object(item))?);
+        }
+        Ok(versions)
     }
 
-    pub fn save(name: &str, content: &str, p2_url: &str) -> Result<(), ComposerError> {
+    /// remove the cache of downloaded archives, of repository metadata and/or
+    /// the mirrors of git repositories
+    pub fn clear(files: bool, repo: bool, vcs: bool) -> Result<(), ComposerError> {
         let cache_dir = home_dir()
             .ok_or(ComposerError::NotFoundHomeDir)?
             .join(CACHE_DIR);
-        let repo_dir = cache_dir.join("repo");
-        let p2_url = String::from(p2_url).replace(":", "-");
-        let p2_url = p2_url.replace("/", "-");
-        let repo_dir = repo_dir.join(p2_url);
-        create_dir_all(&repo_dir)?;
-
-        let name_dir = name.replace('/', "-");
-        let filename = format!("provider-{}.json", name_dir);
-        let final_path = repo_dir.join(filename);
 
-        let mut f = File::create(final_path)?;
-        f.write_all(content.as_bytes())?;
+        let mut dirs = Vec::new();
+        if repo {
+            dirs.push(cache_dir.join("repo"));
+        }
+        if files {
+            dirs.push(cache_dir.join("files"));
+        }
+        if vcs {
+            dirs.push(cache_dir.join("vcs"));
+        }
+        for dir in dirs {
+            if dir.exists() {
+                remove_dir_all(dir)?;
+            }
+        }
 
         Ok(())
     }
-    pub fn read_file(name: &str, p2_url: &str) -> Result<String, ComposerError> {
-        let cache_dir = home_dir()
-            .ok_or(ComposerError::NotFoundHomeDir)?
-            .join(CACHE_DIR);
-        let repo_dir = cache_dir.join("repo");
-        let p2_url = String::from(p2_url).replace(":", "-");
-        let p2_url = p2_url.replace("/", "-");
-        let repo_dir = repo_dir.join(p2_url);
-        create_dir_all(&repo_dir)?;
-
-        let name_dir = name.replace('/', "-");
-        let filename = format!("provider-{}.json", name_dir);
-        let final_path = repo_dir.join(filename);
-
-        let content = read_to_string(final_path)?;
-
-        Ok(content)
-    }
-
-    pub fn clear() -> Result<(), ComposerError> {
-        use std::fs::remove_dir_all;
-        let cache_dir = home_dir()
-            .ok_or(ComposerError::NotFoundHomeDir)?
-            .join(CACHE_DIR);
-        remove_dir_all(cache_dir.join("repo"))?;
+}
 
-        remove_dir_all(cache_dir.join("files"))?;
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ComposerLock {
+    #[serde(rename = "_readme")]
+    #[serde(default = "default_readme")]
+    readme: Vec<String>,
 
-        Ok(())
-    }
+    /// md5 of the keys of composer.json which affect the resolution
+    #[serde(rename = "content-hash")]
+    #[serde(default)]
+    pub(crate) content_hash: String,
 
-    pub fn semver_check(_name: &str, req: &str, version: &str) -> Result<bool, ComposerError> {
-        let mut chars = version.chars();
-        let first_char = chars.next();
-        let version = if let Some('v') = first_char {
-            &version[1..]
-        } else if let Some('V') = first_char {
-            &version[1..]
-        } else {
-            version
-        };
+    pub packages: Vec<Version>,
 
-        let chars = version.chars();
-        let dot_count = chars.filter(|&c| c == '.').count();
-        let version = if dot_count == 1 {
-            format!("{}.0", version)
-        } else {
-            version.to_string()
-        };
+    #[serde(rename = "packages-dev")]
+    #[serde(default)]
+    pub(crate) packages_dev: Vec<Version>,
 
-        let mut req_chars = req.chars();
-        let req_first_char = req_chars.next();
-        let req = if let Some('v') = req_first_char {
-            &req[1..]
-        } else if let Some('V') = req_first_char {
-            &req[1..]
-        } else {
-            req
-        };
-        let req = req.replace("\\u003E", ">");
-        let req = req.replace("\\u003C", "<");
+    #[serde(default)]
+    pub(crate) aliases: Vec<Alias>,
 
-        //println!("now req: {req}");
+    #[serde(rename = "minimum-stability")]
+    #[serde(default = "default_minimum_stability")]
+    pub(crate) minimum_stability: String,
 
-        #[allow(clippy::expect_fun_call)]
-        let version = semver::Version::parse(&version)?;
-        if req.contains("||") {
-            let mut parts = Vec::new();
-            for item in req.split("||") {
-                parts.push(item);
-            }
-            for item in parts.into_iter().rev() {
-                let req = item.trim();
-                let req = VersionReq::parse(req)?;
+    #[serde(rename = "stability-flags")]
+    #[serde(default, deserialize_with = "map_or_empty_list")]
+    pub(crate) stability_flags: IndexMap<String, u8>,
 
-                if req.matches(&version) {
-                    return Ok(true);
-                }
-            }
+    #[serde(rename = "prefer-stable")]
+    #[serde(default)]
+    pub(crate) prefer_stable: bool,
 
-            Ok(false)
-        } else if req.find('|').is_some() {
-            let mut parts = Vec::new();
-            for item in req.split('|') {
-                parts.push(item);
-            }
-            for item in parts.into_iter().rev() {
-                let req = item.trim();
-                let req = VersionReq::parse(req)?;
+    #[serde(rename = "prefer-lowest")]
+    #[serde(default)]
+    pub(crate) prefer_lowest: bool,
 
-                if req.matches(&version) {
-                    return Ok(true);
-                }
-            }
+    /// the platform requirements of require
+    #[serde(default, deserialize_with = "map_or_empty_list")]
+    pub(crate) platform: IndexMap<String, String>,
 
-            Ok(false)
-        } else if req.contains('-') {
-            let mut parts = Vec::new();
-            for item in req.split('-') {
-                parts.push(item);
-            }
-            debug_assert!(parts.len() == 2);
-            let req = format!(">={}", parts[0].trim());
-            let comp1 = Comparator::parse(&req)?;
-            let req = format!("<={}", parts[1].trim());
-            let comp2 = Comparator::parse(&req)?;
-            let req = VersionReq {
-                comparators: vec![comp1, comp2],
-            };
+    /// the platform requirements of require-dev
+    #[serde(rename = "platform-dev")]
+    #[serde(default, deserialize_with = "map_or_empty_list")]
+    pub(crate) platform_dev: IndexMap<String, String>,
 
-            if req.matches(&version) {
-                return Ok(true);
-            }
+    #[serde(rename = "plugin-api-version")]
+    #[serde(default = "default_plugin_api_version")]
+    plugin_api_version: String,
+}
 
-            Ok(false)
-        } else if req.contains('>') && req.contains('<') {
-            let mut parts = Vec::new();
-            for item in req.split(' ') {
-                parts.push(item);
-            }
-            debug_assert!(parts.len() == 2);
-            let req = parts[0].trim();
-            let comp1 = Comparator::parse(req)?;
-            let req = parts[1].trim();
-            let comp2 = Comparator::parse(req)?;
-            let req = VersionReq {
-                comparators: vec![comp1, comp2],
-            };
+fn default_minimum_stability() -> String {
+    Stability::Stable.name().to_owned()
+}
 
-            if req.matches(&version) {
-                return Ok(true);
-            }
+fn default_readme() -> Vec<String> {
+    vec![
+        "This file locks the dependencies of your project to a known state".to_owned(),
+        "Read more about it at https://getcomposer.org/doc/01-basic-usage.md#installing-dependencies".to_owned(),
+        "This file is @generated automatically".to_owned(),
+    ]
+}
 
-            Ok(false)
-        } else {
-            let version_req = VersionReq::parse(&req)?;
+/// the composer plugin api version phpp is compatible with
+fn default_plugin_api_version() -> String {
+    "2.6.0".to_owned()
+}
 
-            Ok(version_req.matches(&version))
-        }
+/// composer writes empty maps as `[]`
+fn map_or_empty_list<'de, D, V>(deserializer: D) -> Result<IndexMap<String, V>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    V: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum MapOrList<V> {
+        Map(IndexMap<String, V>),
+        List(Vec<V>),
     }
-}
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct ComposerLock {
-    pub packages: Vec<Version>,
+    match MapOrList::deserialize(deserializer)? {
+        MapOrList::Map(map) => Ok(map),
+        MapOrList::List(list) if list.is_empty() => Ok(IndexMap::new()),
+        MapOrList::List(_) => Err(serde::de::Error::custom("expected a map")),
+    }
 }
 
 impl ComposerLock {
     pub fn new(versions: Arc<Mutex<Context>>) -> Self {
-        let ls = &versions.lock().unwrap().versions;
+        let ctx = versions.lock().unwrap();
 
         let mut packages = Vec::new();
-        for item in ls.iter() {
+        for item in ctx.versions.iter() {
             if item.name.is_some() {
                 packages.push(item.clone());
             }
@@ -345,14 +263,47 @@ impl ComposerLock {
 
         packages.sort_by(|a, b| a.name.cmp(&b.name));
 
-        Self { packages }
+        let mut packages_dev = ctx.versions_dev.clone();
+        packages_dev.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let stability_flags = ctx
+            .stability_flags
+            .iter()
+            .map(|(name, stability)| (name.to_owned(), stability.priority()))
+            .collect();
+
+        Self {
+            readme: default_readme(),
+            content_hash: String::new(),
+            packages,
+            packages_dev,
+            aliases: ctx.aliases.clone(),
+            minimum_stability: ctx.minimum_stability.name().to_owned(),
+            stability_flags,
+            prefer_stable: ctx.prefer_stable,
+            prefer_lowest: false,
+            platform: IndexMap::new(),
+            platform_dev: IndexMap::new(),
+            plugin_api_version: default_plugin_api_version(),
+        }
     }
 
     pub fn from_file() -> Result<Self, ComposerError> {
         let path = Path::new("./composer.lock");
         let content = read_to_string(path)?;
 
-        let this: Self = serde_json::from_str(&content)?;
+        Self::from_str(&content)
+    }
+
+    pub(crate) fn from_str(content: &str) -> Result<Self, ComposerError> {
+        let mut this: Self = serde_json::from_str(content)?;
+
+        // composer does not write version_normalized into the lock
+        for item in this.packages.iter_mut().chain(this.packages_dev.iter_mut()) {
+            if item.version_normalized.is_empty() {
+                item.version_normalized = normalize(&item.version)?;
+            }
+        }
 
         Ok(this)
     }
@@ -363,11 +314,11 @@ impl ComposerLock {
     ) -> Result<HashSet<String>, ComposerError> {
         let mut this_set = HashSet::new();
 
-        for item in self.packages.iter() {
+        for item in self.installed(true) {
             this_set.insert(item.name.as_ref().unwrap().to_owned());
         }
         let mut new_set = HashSet::new();
-        for item in new_lock.packages.iter() {
+        for item in new_lock.installed(true) {
             new_set.insert(item.name.as_ref().unwrap().to_owned());
         }
 
@@ -376,48 +327,137 @@ impl ComposerLock {
         Ok(difference)
     }
 
+    /// the packages locked in both files, with another version or reference
+    pub fn get_updating_packages(&self, new_lock: &ComposerLock) -> HashSet<String> {
+        let mut updating = HashSet::new();
+        for item in new_lock.installed(true) {
+            let name = item.name.as_deref().unwrap_or_default();
+            if let Some(old) = self.find_version(name) {
+                let reference = |v: &Version| v.dist.as_ref().map(|d| d.reference.clone());
+                if old.version != item.version || reference(old) != reference(item) {
+                    updating.insert(name.to_owned());
+                }
+            }
+        }
+        updating
+    }
+
+    /// the lock file, indented with four spaces like composer does
     pub fn json(&self) -> Result<String, ComposerError> {
-        let res = serde_json::to_string_pretty(&self)?;
+        let mut buf = Vec::new();
+        let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+        let mut ser = serde_json::Serializer::with_formatter(&mut buf, formatter);
+        self.serialize(&mut ser)?;
+
+        let mut res = String::from_utf8(buf).unwrap_or_default();
+        res.push('\n');
 
         Ok(res)
     }
 
-    pub async fn installing(&self) -> Result<(), ComposerError> {
+    /// the packages installed into vendor, `packages-dev` only with `dev`
+    pub(crate) fn installed(&self, dev: bool) -> impl Iterator<Item = &Version> {
+        self.packages
+            .iter()
+            .chain(self.packages_dev.iter().filter(move |_| dev))
+    }
+
+    pub async fn installing(
+        &mut self,
+        dev: bool,
+        downloader: &Downloader,
+        stderr: &mut dyn ErrWriter,
+    ) -> Result<(), ComposerError> {
+        let hashes = self.download(dev, downloader, stderr).await?;
+        for item in self.packages.iter_mut().chain(self.packages_dev.iter_mut()) {
+            let hash = item.name.as_ref().and_then(|name| hashes.get(name));
+            if let (Some(dist), Some(hash)) = (item.dist.as_mut(), hash) {
+                dist.integrity = Some(hash.to_owned());
+            }
+        }
         self.save_file()?;
 
-        self.down_package().await?;
+        self.extract(dev, downloader, stderr)
+    }
 
-        self.install_package()?;
+    /// download and install the locked versions, without touching composer.lock
+    pub async fn install(
+        &self,
+        dev: bool,
+        downloader: &Downloader,
+        stderr: &mut dyn ErrWriter,
+    ) -> Result<(), ComposerError> {
+        self.download(dev, downloader, stderr).await?;
 
-        self.write_psr4()?;
+        self.extract(dev, downloader, stderr)
+    }
+
+    /// download the archives, and check them against the integrity recorded in the lock
+    async fn download(
+        &self,
+        dev: bool,
+        downloader: &Downloader,
+        stderr: &mut dyn ErrWriter,
+    ) -> Result<HashMap<String, String>, ComposerError> {
+        let hashes = downloader
+            .download(self.installed(dev), &files_dir()?, stderr)
+            .await?;
+
+        for item in self.installed(dev) {
+            let name = item.name.as_ref().expect("not found name");
+            let expected = item.dist.as_ref().and_then(|d| d.integrity.as_ref());
+            if let (Some(expected), Some(actual)) = (expected, hashes.get(name)) {
+                if expected != actual {
+                    stderr.write(&format!(
+                        "  - The archive of {}({}) does not match the integrity hash in composer.lock",
+                        name, item.version
+                    ));
+                    return Err(ComposerError::IntegrityMismatch(name.to_owned()));
+                }
+            }
+        }
+
+        Ok(hashes)
+    }
+
+    /// extract the downloaded archives into vendor and write the autoload files
+    fn extract(
+        &self,
+        dev: bool,
+        downloader: &Downloader,
+        stderr: &mut dyn ErrWriter,
+    ) -> Result<(), ComposerError> {
+        self.install_package(dev, downloader, stderr)?;
+
+        self.write_psr4(dev)?;
 
         self.write_installed_versions()?;
 
         self.write_class_loader()?;
         self.write_autoload_real()?;
-        self.write_autoload_static()?;
+        self.write_autoload_static(dev)?;
         self.write_platform_check()?;
         self.write_autoload_classmap()?;
         self.write_autoload()?;
 
-        self.write_autoload_files()?;
+        self.write_autoload_files(dev)?;
 
         Ok(())
     }
-    pub fn update_autoload_files(&self) -> Result<(), ComposerError> {
+    pub fn update_autoload_files(&self, dev: bool) -> Result<(), ComposerError> {
         self.save_file()?;
-        self.write_psr4()?;
+        self.write_psr4(dev)?;
 
         self.write_installed_versions()?;
 
         self.write_class_loader()?;
         self.write_autoload_real()?;
-        self.write_autoload_static()?;
+        self.write_autoload_static(dev)?;
         self.write_platform_check()?;
         self.write_autoload_classmap()?;
         self.write_autoload()?;
 
-        self.write_autoload_files()?;
+        self.write_autoload_files(dev)?;
 
         Ok(())
     }
@@ -430,95 +470,60 @@ impl ComposerLock {
         Ok(())
     }
 
-    async fn down_package(&self) -> Result<(), ComposerError> {
-        use sha1::{Digest, Sha1};
-
-        let cache_dir = home_dir()
-            .ok_or(ComposerError::NotFoundHomeDir)?
-            .join(CACHE_DIR);
-        let repo_dir = cache_dir.join("files");
-        create_dir_all(&repo_dir)?;
-
-        for item in self.packages.iter() {
-            let dist = &item.dist.as_ref().expect("not found dist field");
-
-            let name = item.name.as_ref().expect("not found name");
-
-            let package_dir = repo_dir.join(name.clone());
-            create_dir_all(&package_dir)?;
-
-            let mut hasher = Sha1::new();
-            hasher.update(item.version.as_bytes());
-            let sha1 = hasher.finalize();
-
-            let mut file_name = hex::encode(sha1);
-            file_name.push_str(".zip");
-
-            let file_path = package_dir.join(file_name);
-
-            if file_path.exists() {
-                continue;
-            }
-            let content = reqwest::Client::new()
-                .get(dist.url.clone())
-                .header(USER_AGENT, MY_USER_AGENT)
-                .send()
-                .await?
-                .bytes()
-                .await?;
-
-            let mut f = File::create(file_path)?;
-            f.write_all(&content)?;
-
-            //break;
-            println!("  - Downloading {}({})", name, item.version);
-        }
-
-        Ok(())
-    }
-
-    fn install_package(&self) -> Result<(), ComposerError> {
-        use sha1::{Digest, Sha1};
-
-        let cache_dir = home_dir()
-            .ok_or(ComposerError::NotFoundHomeDir)?
-            .join(CACHE_DIR);
-        let repo_dir = cache_dir.join("files");
+    fn install_package(
+        &self,
+        dev: bool,
+        downloader: &Downloader,
+        stderr: &mut dyn ErrWriter,
+    ) -> Result<(), ComposerError> {
+        let files_dir = files_dir()?;
 
         let vendor_dir = Path::new("./vendor");
         create_dir_all(vendor_dir)?;
 
-        for item in self.packages.iter() {
+        for item in self.installed(dev) {
             let name = item.name.as_ref().expect("not found name");
 
-            println!("  - Installing {}({})", name, item.version);
+            stderr.write(&format!("  - Installing {}({})", name, item.version));
 
             let vendor_item = vendor_dir.join(name.clone());
+            if item.dist.as_ref().is_some_and(|d| d._type == "path") {
+                path::install(item, &vendor_item)?;
+                continue;
+            }
+            // a metapackage, or a source which is not git
+            if item.dist.is_none() && git_source(item).is_none() {
+                continue;
+            }
+            if is_checkout(item, downloader.is_prefer_source()) {
+                let (url, reference) = git_source(item).expect("not found source field");
+                if vendor_item.exists() {
+                    remove_dir_all(&vendor_item)?;
+                }
+                downloader.checkout(url, reference, &vendor_item)?;
+                continue;
+            }
             create_dir_all(&vendor_item)?;
 
-            let package_dir = repo_dir.join(name.clone());
-
-            let mut hasher = Sha1::new();
-            hasher.update(item.version.as_bytes());
-            let sha1 = hasher.finalize();
-
-            let mut file_name = hex::encode(sha1);
-            file_name.push_str(".zip");
-
-            let file_path = package_dir.join(file_name);
+            let file_path = dist_path(&files_dir, item);
 
             let f = File::open(&file_path)?;
 
             let mut archive = zip::ZipArchive::new(f)?;
+            // the files are in a top directory, except in some artifacts
+            let skip = usize::from(has_top_dir(&archive));
 
-            for i in 1..archive.len() {
+            for i in 0..archive.len() {
                 let mut file = archive.by_index(i)?;
                 let outpath = match file.enclosed_name() {
                     Some(path) => path.to_owned(),
                     None => continue,
                 };
 
-                let outpath: PathBuf = outpath.iter().skip(1).collect();
+                let outpath: PathBuf = outpath.iter().skip(skip).collect();
+                if outpath.as_os_str().is_empty() {
+                    continue;
+                }
                 let final_path = vendor_item.join(outpath);
 
                 if file.is_dir() {
@@ -538,9 +543,10 @@ impl ComposerLock {
         Ok(())
     }
 
-    fn write_psr4(&self) -> Result<(), ComposerError> {
+    fn write_psr4(&self, dev: bool) -> Result<(), ComposerError> {
         let mut data = Psr4Data::new()?;
-        data.append_lock(self);
+        data.keep(false);
+        data.append_lock(self, dev);
 
         data.write()
     }
@@ -624,26 +630,41 @@ impl ComposerLock {
         Ok(())
     }
 
-    fn write_autoload_files(&self) -> Result<(), ComposerError> {
+    fn write_autoload_files(&self, dev: bool) -> Result<(), ComposerError> {
         let mut files = FilesData::new()?;
-        files.append_lock(self);
+        files.keep(false);
+        files.append_lock(self, dev);
         files.write()
     }
 
-    fn write_autoload_static(&self) -> Result<(), ComposerError> {
+    fn write_autoload_static(&self, dev: bool) -> Result<(), ComposerError> {
         let mut files = FilesData::new()?;
-        files.append_lock(self);
+        files.keep(false);
+        files.append_lock(self, dev);
 
         let mut psr4 = Psr4Data::new()?;
-        psr4.append_lock(self);
+        psr4.keep(false);
+        psr4.append_lock(self, dev);
 
         let static_data = StaticData::from(&files, &psr4);
 
         static_data.write()
     }
 
+    /// does the locked `item`, or one of its aliases, satisfy `constraint`
+    pub(crate) fn satisfies(&self, item: &Version, constraint: &str) -> bool {
+        let constraint = match Constraint::parse(constraint) {
+            Ok(c) => c,
+            Err(_) => return false,
+        };
+        let name = item.name.as_deref().unwrap_or_default();
+        with_aliases(name, vec![item.clone()], &self.aliases)
+            .iter()
+            .any(|v| constraint.matches(&v.version_normalized))
+    }
+
     pub fn find_version(&self, name: &str) -> Option<&Version> {
-        for item in self.packages.iter() {
+        for item in self.installed(true) {
             if let Some(ref n) = item.name {
                 if n == name {
                     return Some(item);
@@ -659,10 +680,11 @@ pub struct Version {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
     pub(crate) version: String,
+    #[serde(default)]
     pub(crate) version_normalized: String,
 
     #[serde(skip_serializing_if = "Option::is_none")]
-    source: Option<Source>,
+    pub(crate) source: Option<Source>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) dist: Option<Dist>,
@@ -677,40 +699,115 @@ pub struct Version {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) autoload: Option<AutoloadEnum>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) extra: Option<serde_json::Value>,
+
+    /// how the package of a path repository is installed, like `{"symlink": false}`
+    #[serde(rename = "transport-options")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) transport_options: Option<serde_json::Value>,
+
+    /// the pretty and normalized version of the aliased package, when this one is an alias
+    #[serde(skip)]
+    pub(crate) alias_of: Option<(String, String)>,
 }
 
 impl Version {
-    pub fn semver(&self) -> Result<semver::Version, ComposerError> {
-        let mut chars = self.version.chars();
-        let first_char = chars.next();
-        let version = if let Some('v') = first_char {
-            &self.version[1..]
-        } else if let Some('V') = first_char {
-            &self.version[1..]
-        } else {
-            &self.version[..]
-        };
-        let version = semver::Version::parse(version)?;
+    /// the `extra.branch-alias` of this version, like `2.x-dev` for `dev-main`
+    fn branch_alias(&self) -> Option<&str> {
+        let aliases = self.extra.as_ref()?.get("branch-alias")?.as_object()?;
+        aliases
+            .iter()
+            .find(|(branch, _)| branch.eq_ignore_ascii_case(&self.version))
+            .and_then(|(_, alias)| alias.as_str())
+    }
+
+    /// a copy of this version known as `version`
+    fn alias(&self, version: &str, version_normalized: &str) -> Version {
+        let mut alias = self.clone();
+        alias.version = version.to_owned();
+        alias.version_normalized = version_normalized.to_owned();
+        alias.alias_of = Some((self.version.clone(), self.version_normalized.clone()));
+        alias
+    }
+
+    /// the aliased version, or itself
+    pub(crate) fn unalias(mut self) -> Version {
+        if let Some((version, version_normalized)) = self.alias_of.take() {
+            self.version = version;
+            self.version_normalized = version_normalized;
+        }
+        self
+    }
+}
+
+/// an inline alias of composer.json, like `foo/bar: dev-main as 1.5.0`
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq, Eq)]
+pub(crate) struct Alias {
+    pub(crate) package: String,
+    /// the normalized version being aliased
+    pub(crate) version: String,
+    pub(crate) alias: String,
+    pub(crate) alias_normalized: String,
+}
+
+/// add the inline aliases and branch aliases of `name` to its version list
+fn with_aliases(name: &str, list: Vec<Version>, aliases: &[Alias]) -> Vec<Version> {
+    let mut result = Vec::with_capacity(list.len());
+    for item in list {
+        for alias in aliases {
+            if alias.package == name && alias.version == item.version_normalized {
+                result.push(item.alias(&alias.alias, &alias.alias_normalized));
+            }
+        }
 
-        Ok(version)
+        // only numeric branches like `2.x-dev` can be a branch alias
+        if Stability::of(&item.version) == Stability::Dev {
+            if let Some(branch) = item.branch_alias() {
+                if let Ok(normalized) = normalize(branch) {
+                    if normalized.ends_with("-dev") && !normalized.starts_with("dev-") {
+                        result.push(item.alias(branch, &normalized));
+                    }
+                }
+            }
+        }
+
+        result.push(item);
     }
+    result
 }
 
 #[derive(Debug, Deserialize, Clone, Serialize)]
-struct Source {
+pub(crate) struct Source {
     #[serde(rename = "type")]
-    _type: String,
+    pub(crate) _type: String,
 
-    url: String,
-    reference: String,
+    #[serde(serialize_with = "serialize_redacted")]
+    pub(crate) url: String,
+    #[serde(default)]
+    pub(crate) reference: String,
 }
 
 #[derive(Debug, Deserialize, Clone, Serialize)]
 pub struct Dist {
+    #[serde(serialize_with = "serialize_redacted")]
     pub(crate) url: String,
     #[serde(rename = "type")]
     pub(crate) _type: String,
+    /// may be left out by inline `package` repositories
+    #[serde(default)]
     pub(crate) reference: String,
+    /// sha1 of the archive, when the repository provides it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) shasum: Option<String>,
+    /// sha256 of the archive which was installed, see `download::integrity`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) integrity: Option<String>,
+    /// an archive of an artifact repository, read from the disk, never set by
+    /// the metadata of a repository nor by composer.lock
+    #[serde(skip)]
+    pub(crate) local: bool,
 }
 
 #[derive(Debug, Deserialize, Clone, Serialize)]
@@ -761,14 +858,30 @@ enum AutoLoadClassmap {
 
 #[derive(Debug, Default)]
 pub(crate) struct Context {
-    versions: Vec<Version>,
-    hash_set: HashSet<String>,
-    pub(crate) first_package: Option<Version>,
+    pub(crate) versions: Vec<Version>,
+    /// the versions only required by require-dev
+    pub(crate) versions_dev: Vec<Version>,
+    /// all loaded versions, by package name
+    pub(crate) pool: HashMap<String, Vec<Version>>,
+    /// `name@version` whose requirements have been loaded
+    expanded: HashSet<String>,
     pub(crate) php_extensions: Vec<String>,
     pub(crate) php_version: String,
-    pub(crate) php_version_error: Vec<(String, String)>,
-    pub(crate) php_extensions_error: Vec<(String, String)>,
-    pub p2_url: String,
+    /// the repositories, by priority
+    pub(crate) repositories: Vec<RepositoryConfig>,
+    /// read from `repositories` by `P2::down_all`
+    pub(crate) repository: Option<Arc<RepositorySet>>,
+    pub(crate) cache_dir: PathBuf,
+    /// shared by all requests, to reuse connections
+    pub(crate) http: Http,
+    /// cached metadata older than this is revalidated, never when `None`
+    pub(crate) metadata_ttl: Option<Duration>,
+    pub(crate) minimum_stability: Stability,
+    pub(crate) prefer_stable: bool,
+    /// stability allowed for single root requirements, like `foo/bar: ^2.0@beta`
+    pub(crate) stability_flags: IndexMap<String, Stability>,
+    /// inline aliases of the root requirements
+    pub(crate) aliases: Vec<Alias>,
 }
 
 impl Context {
@@ -776,10 +889,44 @@ impl Context {
         Ok(Context {
             php_version: Self::php_version()?,
             php_extensions: Self::php_extensions(),
+            cache_dir: cache_dir()?,
+            metadata_ttl: Some(Duration::ZERO),
             ..Default::default()
         })
     }
 
+    /// mark the versions of `name` matching `version` as loaded, and return the
+    /// requirements of those not loaded before
+    fn expand(&mut self, name: &str, version: Option<&str>) -> Vec<(String, Option<String>)> {
+        let constraint = version.map(Constraint::parse);
+        let mut deps = Vec::new();
+        for item in self.pool.get(name).into_iter().flatten() {
+            match constraint {
+                Some(Ok(ref c)) if !c.matches(&item.version_normalized) => continue,
+                Some(Err(_)) => break,
+                _ => {}
+            }
+            if !self.expanded.insert(format!("{}@{}", name, item.version)) {
+                continue;
+            }
+            if let Some(Require::Map(map)) = &item.require {
+                for (dep_name, version) in map.iter() {
+                    if !is_platform(dep_name) {
+                        deps.push((dep_name.to_owned(), Some(version.to_owned())));
+                    }
+                }
+            }
+        }
+        deps
+    }
+
+    /// keep `item` at its locked version, no other version of it is loaded
+    pub(crate) fn pin(&mut self, item: Version) {
+        let name = item.name.clone().unwrap_or_default();
+        let list = with_aliases(&name, vec![item], &self.aliases);
+        self.pool.insert(name, list);
+    }
+
     fn php_version() -> Result<String, ComposerError> {
         //return Ok("7.0".to_owned());
         // PHP 8.1.2-1ubuntu2.17 (cli) (built: May  1 2024 10:10:07) (NTS)
@@ -815,66 +962,50 @@ impl Context {
         let extensions: Vec<String> = stdout.lines().map(|s| s.to_string()).collect();
         extensions
     }
+}
+
+/// `php`, `ext-*` and `lib-*` requirements are provided by the system, not by a package
+pub(crate) fn is_platform(name: &str) -> bool {
+    name == "php" || name.starts_with("ext-") || name.starts_with("lib-")
+}
 
-    fn exists_extension(&self, extension: &str) -> bool {
-        for item in self.php_extensions.iter() {
-            if item == extension {
-                return true;
+/// undo `MetadataMinifier::minify` of composer, every version only has the keys
+/// which changed from the previous one, and `__unset` for the removed keys
+fn expand(list: Vec<serde_json::Map<String, Value>>) -> Vec<serde_json::Map<String, Value>> {
+    let mut expanded = Vec::new();
+    let mut current = serde_json::Map::new();
+    for item in list {
+        for (key, value) in item {
+            if value.as_str() == Some("__unset") {
+                current.remove(&key);
+            } else {
+                current.insert(key, value);
             }
         }
-
-        false
+        expanded.push(current.clone());
     }
+    expanded
+}
+
+/// whether every file of `archive` is in one top directory
+fn has_top_dir<R: std::io::Read + std::io::Seek>(archive: &zip::ZipArchive<R>) -> bool {
+    let mut tops = archive.file_names().map(|name| {
+        let mut parts = name.splitn(2, '/');
+        (parts.next().unwrap_or_default(), parts.next().is_some())
+    });
+    let Some((top, true)) = tops.next() else {
+        return false;
+    };
+    tops.all(|(other, nested)| other == top && nested)
+}
+
+pub(crate) fn cache_dir() -> Result<PathBuf, ComposerError> {
+    Ok(home_dir()
+        .ok_or(ComposerError::NotFoundHomeDir)?
+        .join(CACHE_DIR))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    // #[tokio::test]
-    // async fn test_deser() {
-    //     let mut url = String::from("");
-    //     let name = "guzzlehttp/guzzle";
-    //     url.push_str(name);
-    //     url.push_str(".json");
-
-    //     let json = reqwest::Client::new()
-    //         .get(url)
-    //         .send()
-    //         .await
-    //         .unwrap()
-    //         .text()
-    //         .await
-    //         .unwrap();
-
-    //     let res: P2 = serde_json::from_str(&json).unwrap();
-
-    //     println!("{res:?}");
-    // }
-
-    #[test]
-    fn test_semver() {
-        assert!(P2::semver_check("name", "^7.0| ^8.0", "7.2.3").unwrap());
-        assert!(P2::semver_check("name", "^7.0| ^8.0", "8.2.3").unwrap());
-        assert!(!P2::semver_check("name", "^7.0| ^8.0", "9.2.3").unwrap());
-        assert!(!P2::semver_check("name", "^7.0|| ^8.0", "9.2.3").unwrap());
-        assert!(P2::semver_check("name", "^7.0| ^8.0", "8.0").unwrap());
-        assert!(P2::semver_check("name", ">=7.4", "8.0").unwrap());
-        assert!(!P2::semver_check("name", ">=8.1", "8.0").unwrap());
-        //assert!(P2::semver_check("5.1.0-RC1", "5.1.0-RC1"));
-
-        let chars = "1.2.4".chars();
-        let dot_count = chars.filter(|&c| c == '.').count();
-        assert_eq!(dot_count, 2);
-
-        let version = semver::Version::parse("5.0.8").unwrap();
-        assert!(version.pre == Prerelease::EMPTY);
-    }
-
-    // #[test]
-    // fn test_php_version() {
-    //     let v = Context::php_version().unwrap();
-
-    //     assert_eq!(v, "8.1.2".to_owned());
-    // }
+/// the cache of the downloaded dist archives
+fn files_dir() -> Result<PathBuf, ComposerError> {
+    Ok(cache_dir()?.join("files"))
 }
@@ -0,0 +1,874 @@
+//! a composer repository, described by its `packages.json`
+//!
+//! The root file tells where the metadata of a package is: `metadata-url` for
+//! composer 2 repositories, `providers-url` with the hashes listed in the
+//! `provider-includes` for composer 1 repositories, or the `packages` and
+//! `includes` of the root file itself, as written by satis. A repository
+//! without `packages.json` is read like packagist, from `p2/<name>.json`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{create_dir_all, read_to_string, remove_file, File},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use regex::Regex;
+use reqwest::{
+    header::{HeaderMap, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    Response, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    auth::redact,
+    config::RepositoryConfig,
+    error::ComposerError,
+    http::Http,
+    package::{Version, P2},
+};
+
+const DEFAULT_METADATA_URL: &str = "/p2/%package%.json";
+
+pub(crate) mod artifact;
+pub(crate) mod package;
+pub(crate) mod path;
+pub(crate) mod vcs;
+
+use artifact::ArtifactRepository;
+use package::PackageRepository;
+use path::PathRepository;
+use vcs::VcsRepository;
+
+#[derive(Debug)]
+enum Repository {
+    Composer(Box<ComposerRepository>),
+    Path(PathRepository),
+    Vcs(VcsRepository),
+    Artifact(ArtifactRepository),
+    Package(PackageRepository),
+}
+
+impl Repository {
+    async fn versions(&self, name: &str, dev: bool) -> Result<Vec<Version>, ComposerError> {
+        match self {
+            Repository::Composer(repo) => repo.versions(name, dev).await,
+            Repository::Path(repo) => Ok(repo.versions(name)),
+            Repository::Vcs(repo) => Ok(repo.versions(name)),
+            Repository::Artifact(repo) => Ok(repo.versions(name)),
+            Repository::Package(repo) => Ok(repo.versions(name)),
+        }
+    }
+}
+
+/// the repositories of composer.json, the first has the highest priority
+#[derive(Debug)]
+pub(crate) struct RepositorySet {
+    repos: Vec<Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    repo: Repository,
+    canonical: bool,
+    only: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl RepositorySet {
+    pub(crate) async fn new(
+        configs: &[RepositoryConfig],
+        http: Http,
+        cache_dir: PathBuf,
+        ttl: Option<Duration>,
+    ) -> Result<Self, ComposerError> {
+        let mut repos = Vec::new();
+        for config in configs {
+            let repo = match config._type.as_str() {
+                "composer" => Repository::Composer(Box::new(
+                    ComposerRepository::new(&config.url, http.clone(), cache_dir.clone(), ttl)
+                        .await?,
+                )),
+                "path" => Repository::Path(PathRepository::new(&config.url, &config.options)?),
+                "package" => {
+                    Repository::Package(PackageRepository::new(config.options.get("package"))?)
+                }
+                "artifact" => Repository::Artifact(ArtifactRepository::new(&config.url)?),
+                "vcs" | "git" => {
+                    // git runs outside of the async workers
+                    let (url, cache_dir) = (config.url.clone(), cache_dir.clone());
+                    let auth = http.credentials();
+                    let repo = tokio::task::spawn_blocking(move || {
+                        VcsRepository::new(&url, &cache_dir, &auth)
+                    })
+                    .await??;
+                    Repository::Vcs(repo)
+                }
+                _ => {
+                    return Err(ComposerError::InvalidJson(format!(
+                        "the repository type {} is not supported",
+                        config._type
+                    )))
+                }
+            };
+            repos.push(Entry {
+                repo,
+                canonical: config.canonical,
+                only: globs(&config.only),
+                exclude: globs(&config.exclude),
+            });
+        }
+
+        Ok(RepositorySet { repos })
+    }
+
+    /// the versions of `name` from the first repository which has it, and from
+    /// the next ones while the repositories are not canonical
+    ///
+    /// A version found in a repository hides the same version in the next ones.
+    pub(crate) async fn versions(
+        &self,
+        name: &str,
+        dev: bool,
+    ) -> Result<Vec<Version>, ComposerError> {
+        let mut list: Vec<Version> = Vec::new();
+        for entry in self.repos.iter() {
+            if !entry.only.is_empty() && !entry.only.iter().any(|p| p.is_match(name)) {
+                continue;
+            }
+            if entry.exclude.iter().any(|p| p.is_match(name)) {
+                continue;
+            }
+
+            let versions = entry.repo.versions(name, dev).await?;
+            if versions.is_empty() {
+                continue;
+            }
+            for item in versions {
+                if !list
+                    .iter()
+                    .any(|v| v.version_normalized == item.version_normalized)
+                {
+                    list.push(item);
+                }
+            }
+            if entry.canonical {
+                break;
+            }
+        }
+
+        Ok(list)
+    }
+
+    /// the composer repositories, which can be searched
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &ComposerRepository> {
+        self.repos.iter().filter_map(|entry| match &entry.repo {
+            Repository::Composer(repo) => Some(repo.as_ref()),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ComposerRepository {
+    url: String,
+    http: Http,
+    cache_dir: PathBuf,
+    /// cached metadata older than this is revalidated, never when `None`
+    ttl: Option<Duration>,
+    metadata_url: Option<String>,
+    /// the repository has no other packages than these, when it lists them
+    available_packages: Option<HashSet<String>>,
+    available_package_patterns: Vec<Regex>,
+    providers_url: Option<String>,
+    /// the sha256 of the metadata file of every package, by name
+    providers: HashMap<String, String>,
+    /// the versions listed in `packages.json` and its includes, by name
+    packages: HashMap<String, Vec<Map<String, Value>>>,
+    search: Option<String>,
+}
+
+/// the keys of `packages.json` which are used
+#[derive(Debug, Default, Deserialize)]
+struct Root {
+    #[serde(default)]
+    packages: Value,
+    #[serde(rename = "metadata-url")]
+    metadata_url: Option<String>,
+    #[serde(rename = "available-packages")]
+    available_packages: Option<Vec<String>>,
+    #[serde(rename = "available-package-patterns")]
+    #[serde(default)]
+    available_package_patterns: Vec<String>,
+    #[serde(rename = "providers-url")]
+    providers_url: Option<String>,
+    #[serde(rename = "provider-includes")]
+    #[serde(default)]
+    provider_includes: HashMap<String, Hashes>,
+    #[serde(default)]
+    includes: HashMap<String, Hashes>,
+    search: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Hashes {
+    sha256: Option<String>,
+    sha1: Option<String>,
+}
+
+/// a provider include, or the metadata file of a package in a composer 1 repository
+#[derive(Debug, Default, Deserialize)]
+struct Listing {
+    #[serde(default)]
+    providers: HashMap<String, Hashes>,
+    #[serde(default)]
+    packages: Value,
+}
+
+impl ComposerRepository {
+    /// read the `packages.json` of the repository at `url`
+    pub(crate) async fn new(
+        url: &str,
+        http: Http,
+        cache_dir: PathBuf,
+        ttl: Option<Duration>,
+    ) -> Result<Self, ComposerError> {
+        let mut repo = ComposerRepository {
+            url: url.trim_end_matches('/').to_owned(),
+            http,
+            cache_dir,
+            ttl,
+            metadata_url: None,
+            available_packages: None,
+            available_package_patterns: Vec::new(),
+            providers_url: None,
+            providers: HashMap::new(),
+            packages: HashMap::new(),
+            search: None,
+        };
+
+        let root_url = format!("{}/packages.json", repo.url);
+        let root: Root = match repo.fetch("packages.json", &root_url, ttl).await? {
+            Some(json) => serde_json::from_str(&json)?,
+            None => Root {
+                metadata_url: Some(DEFAULT_METADATA_URL.to_owned()),
+                ..Default::default()
+            },
+        };
+
+        repo.metadata_url = root.metadata_url;
+        repo.available_packages = root
+            .available_packages
+            .map(|list| list.iter().map(|name| name.to_lowercase()).collect());
+        repo.available_package_patterns = globs(&root.available_package_patterns);
+        repo.search = root.search;
+        repo.add_packages(root.packages);
+
+        // the hashed files never change, they are only fetched once
+        for (path, hashes) in root.includes {
+            let url = repo.resolve(&path, hashes.sha1.as_deref().unwrap_or_default());
+            if let Some(json) = repo.fetch(&cache_name(&url), &url, None).await? {
+                let listing: Listing = serde_json::from_str(&json)?;
+                repo.add_packages(listing.packages);
+            }
+        }
+
+        // composer 1 providers are only used when there is no metadata url
+        if repo.metadata_url.is_none() {
+            repo.providers_url = root.providers_url;
+            for (path, hashes) in root.provider_includes {
+                let url = repo.resolve(&path, hashes.sha256.as_deref().unwrap_or_default());
+                if let Some(json) = repo.fetch(&cache_name(&url), &url, None).await? {
+                    let listing: Listing = serde_json::from_str(&json)?;
+                    for (name, hashes) in listing.providers {
+                        let hash = hashes.sha256.unwrap_or_default();
+                        repo.providers.insert(name.to_lowercase(), hash);
+                    }
+                }
+            }
+        }
+
+        Ok(repo)
+    }
+
+    /// the versions of `name`, with the versions of `<name>~dev.json` too when `dev`
+    pub(crate) async fn versions(
+        &self,
+        name: &str,
+        dev: bool,
+    ) -> Result<Vec<Version>, ComposerError> {
+        let mut list = Vec::new();
+        if !self.provides(name) {
+            return Ok(list);
+        }
+
+        if let Some(versions) = self.packages.get(name) {
+            list.extend(P2::versions(versions.clone())?);
+        }
+
+        if let Some(metadata_url) = &self.metadata_url {
+            let mut files = vec![name.to_owned()];
+            // the dev versions are in another file
+            if dev {
+                files.push(format!("{}~dev", name));
+            }
+            for file in files {
+                let url = self.resolve(&metadata_url.replace("%package%", &file), "");
+                let cache = format!("provider-{}.json", file.replace('/', "-"));
+                if let Some(json) = self.fetch(&cache, &url, self.ttl).await? {
+                    list.extend(P2::parse(name, &json)?);
+                }
+            }
+        } else if let (Some(providers_url), Some(hash)) =
+            (&self.providers_url, self.providers.get(name))
+        {
+            let url = self.resolve(&providers_url.replace("%package%", name), hash);
+            if let Some(json) = self.fetch(&cache_name(&url), &url, None).await? {
+                let listing: Listing = serde_json::from_str(&json)?;
+                let mut packages = by_name(listing.packages);
+                list.extend(P2::versions(packages.remove(name).unwrap_or_default())?);
+            }
+        }
+
+        for item in list.iter_mut() {
+            item.name = Some(name.to_owned());
+        }
+        Ok(list)
+    }
+
+    /// the url of the search api for `query`
+    pub(crate) fn search_url(&self, query: &str) -> Option<String> {
+        let search = self.search.as_ref()?;
+        let query: String = query
+            .bytes()
+            .map(|b| match b {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                    (b as char).to_string()
+                }
+                _ => format!("%{:02X}", b),
+            })
+            .collect();
+        Some(self.resolve(&search.replace("%query%", &query).replace("%type%", ""), ""))
+    }
+
+    /// the names of the packages the repository lists, when it has no search api
+    pub(crate) fn names(&self) -> Vec<String> {
+        let mut names: HashSet<&String> = self.packages.keys().collect();
+        names.extend(self.providers.keys());
+        names.extend(self.available_packages.iter().flatten());
+        let mut names: Vec<String> = names.into_iter().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// GET `url`, with the client of the repository
+    pub(crate) async fn get(&self, url: &str) -> Result<Response, ComposerError> {
+        self.http.get(url, HeaderMap::new()).await
+    }
+
+    fn provides(&self, name: &str) -> bool {
+        if self.available_packages.is_none() && self.available_package_patterns.is_empty() {
+            return true;
+        }
+        self.available_packages
+            .as_ref()
+            .is_some_and(|list| list.contains(name))
+            || self
+                .available_package_patterns
+                .iter()
+                .any(|pattern| pattern.is_match(name))
+    }
+
+    fn add_packages(&mut self, packages: Value) {
+        for (name, list) in by_name(packages) {
+            self.packages.entry(name).or_default().extend(list);
+        }
+    }
+
+    /// an absolute url of a path found in `packages.json`, with `%hash%` replaced
+    fn resolve(&self, path: &str, hash: &str) -> String {
+        let path = path.replace("%hash%", hash);
+        if path.contains("://") {
+            path
+        } else if path.starts_with('/') {
+            format!("{}{}", origin(&self.url), path)
+        } else {
+            format!("{}/{}", self.url, path)
+        }
+    }
+
+    /// the content of `url`, from the file `cache` when it is younger than `ttl`,
+    /// `None` when the repository does not have it
+    async fn fetch(
+        &self,
+        cache: &str,
+        url: &str,
+        ttl: Option<Duration>,
+    ) -> Result<Option<String>, ComposerError> {
+        let path = self.cache_path(cache)?;
+        let cached = if path.exists() {
+            Some(read_to_string(&path)?)
+        } else {
+            None
+        };
+        let mut meta = CacheMeta::read(&path);
+        let fresh = ttl.is_none_or(|ttl| meta.age() < ttl);
+
+        let json = match cached {
+            Some(json) if fresh => return Ok(Some(json)),
+            cached => {
+                let validators = cached.as_ref().map(|_| &meta);
+                match self.down(url, validators).await? {
+                    Fetched::Modified(json, new_meta) => {
+                        let mut f = File::create(&path)?;
+                        f.write_all(json.as_bytes())?;
+                        meta = new_meta;
+                        Some(json)
+                    }
+                    Fetched::NotModified => cached,
+                    // the repository dropped it, a cached copy is stale
+                    Fetched::NotFound => {
+                        for file in [path.clone(), CacheMeta::path(&path)] {
+                            match remove_file(file) {
+                                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                                    return Err(e.into())
+                                }
+                                _ => {}
+                            }
+                        }
+                        return Ok(None);
+                    }
+                }
+            }
+        };
+        meta.fetched = now();
+        meta.save(&path)?;
+
+        Ok(json)
+    }
+
+    /// fetch `url`, only if it changed since the response described by `validators`
+    async fn down(
+        &self,
+        url: &str,
+        validators: Option<&CacheMeta>,
+    ) -> Result<Fetched, ComposerError> {
+        let mut headers = HeaderMap::new();
+        if let Some(meta) = validators {
+            if let Some(value) = meta.last_modified.as_ref().and_then(|v| v.parse().ok()) {
+                headers.insert(IF_MODIFIED_SINCE, value);
+            }
+            if let Some(value) = meta.etag.as_ref().and_then(|v| v.parse().ok()) {
+                headers.insert(IF_NONE_MATCH, value);
+            }
+        }
+        let response = self.http.get(url, headers).await?;
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Fetched::NotModified);
+        }
+        match response.status() {
+            StatusCode::NOT_FOUND | StatusCode::GONE => return Ok(Fetched::NotFound),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                return Err(ComposerError::Unauthorized(redact(url)))
+            }
+            status if !status.is_success() => {
+                return Err(ComposerError::HttpStatus(status.as_u16(), redact(url)))
+            }
+            _ => {}
+        }
+
+        let header = |key| {
+            response
+                .headers()
+                .get(key)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned)
+        };
+        let meta = CacheMeta {
+            last_modified: header(LAST_MODIFIED),
+            etag: header(ETAG),
+            fetched: now(),
+        };
+        let json = response.text().await?;
+
+        Ok(Fetched::Modified(json, meta))
+    }
+
+    /// `repo/<url of the repository>/<name>`
+    fn cache_path(&self, name: &str) -> Result<PathBuf, ComposerError> {
+        let repo_dir = self
+            .cache_dir
+            .join("repo")
+            .join(redact(&self.url).replace([':', '/'], "-"));
+        create_dir_all(&repo_dir)?;
+
+        Ok(repo_dir.join(name))
+    }
+}
+
+/// the answer of the repository to a metadata request
+enum Fetched {
+    Modified(String, CacheMeta),
+    NotModified,
+    NotFound,
+}
+
+/// how a cached metadata file was fetched, stored next to it
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheMeta {
+    #[serde(rename = "last-modified")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    /// the unix time of the last request
+    #[serde(default)]
+    fetched: u64,
+}
+
+impl CacheMeta {
+    fn path(json_path: &Path) -> PathBuf {
+        json_path.with_extension("meta")
+    }
+
+    /// a missing or broken file is an entry which was never validated
+    fn read(json_path: &Path) -> Self {
+        read_to_string(Self::path(json_path))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, json_path: &Path) -> Result<(), ComposerError> {
+        let mut f = File::create(Self::path(json_path))?;
+        f.write_all(serde_json::to_string(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    fn age(&self) -> Duration {
+        Duration::from_secs(now().saturating_sub(self.fetched))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// `{"foo/bar": {"1.0.0": {...}}}` of composer 1 files as lists of versions,
+/// an empty list like the `"packages": []` of packagist is no package
+fn by_name(packages: Value) -> HashMap<String, Vec<Map<String, Value>>> {
+    let Value::Object(packages) = packages else {
+        return HashMap::new();
+    };
+    packages
+        .into_iter()
+        .map(|(name, versions)| {
+            let list = match versions {
+                Value::Object(map) => map.into_iter().map(|(_, v)| v).collect(),
+                Value::Array(list) => list,
+                _ => Vec::new(),
+            };
+            let list = list
+                .into_iter()
+                .filter_map(|v| match v {
+                    Value::Object(map) => Some(map),
+                    _ => None,
+                })
+                .collect();
+            (name.to_lowercase(), list)
+        })
+        .collect()
+}
+
+/// the cache file of a hashed url, like `p-provider-2024$abc.json`
+fn cache_name(url: &str) -> String {
+    let start = url.find("://").map_or(0, |i| i + 3);
+    let path = match url[start..].find('/') {
+        Some(i) => &url[start + i + 1..],
+        None => "",
+    };
+    path.replace(['/', ':', '?', '&', '='], "-")
+}
+
+/// package name patterns like `foo/*`, matched case insensitively
+fn globs(patterns: &[String]) -> Vec<Regex> {
+    patterns
+        .iter()
+        .filter_map(|pattern| {
+            let pattern = regex::escape(&pattern.to_lowercase()).replace("\\*", ".*");
+            Regex::new(&format!("^{}$", pattern)).ok()
+        })
+        .collect()
+}
+
+/// `https://example.org:8080` of `https://example.org:8080/foo/bar`
+pub(crate) fn origin(url: &str) -> &str {
+    let start = url.find("://").map_or(0, |i| i + 3);
+    match url[start..].find('/') {
+        Some(end) => &url[..start + end],
+        None => url,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::{Method::GET, MockServer};
+    use serde_json::json;
+
+    use super::*;
+
+    async fn repository(server: &MockServer, root: Value, cache_dir: &Path) -> ComposerRepository {
+        server.mock(|when, then| {
+            when.method(GET).path("/packages.json");
+            then.status(200).json_body(root);
+        });
+        ComposerRepository::new(
+            &server.base_url(),
+            Http::insecure(),
+            cache_dir.to_path_buf(),
+            Some(Duration::ZERO),
+        )
+        .await
+        .unwrap()
+    }
+
+    fn version(version: &str) -> Value {
+        json!({ "version": version, "version_normalized": format!("{version}.0") })
+    }
+
+    #[tokio::test]
+    async fn test_metadata_url() {
+        let server = MockServer::start();
+        let bar = server.mock(|when, then| {
+            when.method(GET).path("/meta/foo/bar.json");
+            then.status(200)
+                .json_body(json!({ "packages": { "foo/bar": [version("1.0.0")] } }));
+        });
+        let cache_dir = tempfile::tempdir().unwrap();
+        let repo = repository(
+            &server,
+            json!({
+                "packages": [],
+                "metadata-url": "/meta/%package%.json",
+                "available-packages": ["foo/bar"],
+                "available-package-patterns": ["baz/*"],
+                "search": "/search.json?q=%query%&type=%type%",
+            }),
+            cache_dir.path(),
+        )
+        .await;
+
+        let list = repo.versions("foo/bar", false).await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].name.as_deref(), Some("foo/bar"));
+        bar.assert();
+
+        // not requested at all
+        let other = server.mock(|when, then| {
+            when.method(GET).path("/meta/foo/other.json");
+            then.status(200);
+        });
+        assert!(repo.versions("foo/other", false).await.unwrap().is_empty());
+        other.assert_hits(0);
+        assert!(repo.provides("baz/qux"));
+
+        assert_eq!(
+            repo.search_url("foo bar").unwrap(),
+            format!("{}/search.json?q=foo%20bar&type=", server.base_url())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_providers() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/p/provider-all$abc.json");
+            then.status(200)
+                .json_body(json!({ "providers": { "foo/bar": { "sha256": "def" } } }));
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/p/foo/bar$def.json");
+            then.status(200).json_body(json!({
+                "packages": { "foo/bar": { "1.0.0": version("1.0.0"), "1.1.0": version("1.1.0") } }
+            }));
+        });
+        let cache_dir = tempfile::tempdir().unwrap();
+        let repo = repository(
+            &server,
+            json!({
+                "packages": [],
+                "providers-url": "/p/%package%$%hash%.json",
+                "provider-includes": { "p/provider-all$%hash%.json": { "sha256": "abc" } },
+            }),
+            cache_dir.path(),
+        )
+        .await;
+
+        let list = repo.versions("foo/bar", false).await.unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(repo.versions("foo/other", false).await.unwrap().is_empty());
+        assert_eq!(repo.names(), vec!["foo/bar".to_owned()]);
+        assert!(repo.search_url("foo").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_satis() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/include/all$123.json");
+            then.status(200)
+                .json_body(json!({ "packages": { "foo/baz": { "2.0.0": version("2.0.0") } } }));
+        });
+        let cache_dir = tempfile::tempdir().unwrap();
+        let repo = repository(
+            &server,
+            json!({
+                "packages": { "foo/bar": { "1.0.0": version("1.0.0") } },
+                "includes": { "include/all$123.json": { "sha1": "123" } },
+            }),
+            cache_dir.path(),
+        )
+        .await;
+
+        assert_eq!(repo.versions("foo/bar", false).await.unwrap().len(), 1);
+        assert_eq!(repo.versions("foo/baz", false).await.unwrap().len(), 1);
+        assert_eq!(
+            repo.names(),
+            vec!["foo/bar".to_owned(), "foo/baz".to_owned()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_status() {
+        // the error of the status, unauthorized for missing or refused credentials
+        fn is_error<T>(result: Result<T, ComposerError>, status: u16, url: &str) -> bool {
+            match result {
+                Err(ComposerError::Unauthorized(u)) => (status == 401 || status == 403) && u == url,
+                Err(ComposerError::HttpStatus(s, u)) => s == status && u == url,
+                _ => false,
+            }
+        }
+
+        for status in [401, 403, 500] {
+            let server = MockServer::start();
+            server.mock(|when, then| {
+                when.method(GET).path("/packages.json");
+                then.status(status);
+            });
+            let cache_dir = tempfile::tempdir().unwrap();
+            let repo = ComposerRepository::new(
+                &server.base_url(),
+                Http::insecure(),
+                cache_dir.path().to_path_buf(),
+                None,
+            )
+            .await;
+            let url = server.url("/packages.json");
+            assert!(is_error(repo, status, &url));
+
+            let server = MockServer::start();
+            server.mock(|when, then| {
+                when.method(GET).path("/p2/foo/bar.json");
+                then.status(status);
+            });
+            let cache_dir = tempfile::tempdir().unwrap();
+            let repo = repository(
+                &server,
+                json!({"metadata-url": "/p2/%package%.json"}),
+                cache_dir.path(),
+            )
+            .await;
+            let url = server.url("/p2/foo/bar.json");
+            assert!(is_error(
+                repo.versions("foo/bar", false).await,
+                status,
+                &url
+            ));
+        }
+
+        // only not found and gone are a missing package
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/p2/foo/gone.json");
+            then.status(410);
+        });
+        let cache_dir = tempfile::tempdir().unwrap();
+        let repo = repository(
+            &server,
+            json!({"metadata-url": "/p2/%package%.json"}),
+            cache_dir.path(),
+        )
+        .await;
+        assert!(repo.versions("foo/gone", false).await.unwrap().is_empty());
+        assert!(repo
+            .versions("foo/missing", false)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_removed() {
+        let server = MockServer::start();
+        let mut bar = server.mock(|when, then| {
+            when.method(GET).path("/p2/foo/bar.json");
+            then.status(200)
+                .json_body(json!({ "packages": { "foo/bar": [version("1.0.0")] } }));
+        });
+        let url = server.base_url();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let new = |ttl| {
+            ComposerRepository::new(&url, Http::insecure(), cache_dir.path().to_path_buf(), ttl)
+        };
+        let repo = new(Some(Duration::ZERO)).await.unwrap();
+        assert_eq!(repo.versions("foo/bar", false).await.unwrap().len(), 1);
+        let cached = repo.cache_path("provider-foo-bar.json").unwrap();
+        assert!(cached.exists());
+
+        bar.delete();
+        server.mock(|when, then| {
+            when.method(GET).path("/p2/foo/bar.json");
+            then.status(404);
+        });
+        assert!(repo.versions("foo/bar", false).await.unwrap().is_empty());
+        assert!(!cached.exists() && !CacheMeta::path(&cached).exists());
+
+        // not served from the cache by a later run
+        let repo = new(None).await.unwrap();
+        assert!(repo.versions("foo/bar", false).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let repo = ComposerRepository {
+            url: "https://example.org/satis".to_owned(),
+            http: Http::default(),
+            cache_dir: PathBuf::new(),
+            ttl: None,
+            metadata_url: None,
+            available_packages: None,
+            available_package_patterns: Vec::new(),
+            providers_url: None,
+            providers: HashMap::new(),
+            packages: HashMap::new(),
+            search: None,
+        };
+        assert_eq!(
+            repo.resolve("/p2/foo.json", ""),
+            "https://example.org/p2/foo.json"
+        );
+        assert_eq!(
+            repo.resolve("p/foo$%hash%.json", "abc"),
+            "https://example.org/satis/p/foo$abc.json"
+        );
+        assert_eq!(
+            repo.resolve("https://cdn.org/foo.json", ""),
+            "https://cdn.org/foo.json"
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! an `artifact` repository, a directory of package zip archives
+//!
+//! The composer.json of a package is read from its archive, at the root or in
+//! its top directory, and must have a `version`. Packages are installed from
+//! the archive in the directory.
+
+use std::{
+    fs::{canonicalize, read, read_dir},
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use serde_json::{json, Map, Value};
+
+use crate::{constraint::normalize, error::ComposerError, package::Version};
+
+#[derive(Debug)]
+pub(crate) struct ArtifactRepository {
+    packages: Vec<Version>,
+}
+
+impl ArtifactRepository {
+    /// read the composer.json of every zip archive in the directory `url`
+    pub(crate) fn new(url: &str) -> Result<Self, ComposerError> {
+        use sha1::{Digest, Sha1};
+
+        let mut files: Vec<_> = read_dir(url)?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().is_some_and(|e| e == "zip"))
+            .collect();
+        files.sort();
+
+        let mut packages = Vec::new();
+        for file in files {
+            let content = read(&file)?;
+            let Some(mut json) = composer_json(&content) else {
+                continue;
+            };
+            if !json.get("name").is_some_and(Value::is_string) {
+                continue;
+            }
+            let Some(version) = json.get("version").and_then(Value::as_str) else {
+                continue;
+            };
+            let Ok(normalized) = normalize(version) else {
+                continue;
+            };
+
+            let shasum = hex::encode(Sha1::digest(&content));
+            json.insert("version_normalized".to_owned(), json!(normalized));
+            json.insert(
+                "dist".to_owned(),
+                json!({
+                    "type": "zip",
+                    "url": file.to_string_lossy().trim_start_matches("./"),
+                    "reference": shasum,
+                    "shasum": shasum,
+                }),
+            );
+
+            let mut item: Version = serde_json::from_value(Value::Object(json))?;
+            item.name = item.name.map(|name| name.to_lowercase());
+            if let Some(dist) = item.dist.as_mut() {
+                dist.local = true;
+            }
+            packages.push(item);
+        }
+
+        Ok(ArtifactRepository { packages })
+    }
+
+    pub(crate) fn versions(&self, name: &str) -> Vec<Version> {
+        self.packages
+            .iter()
+            .filter(|item| item.name.as_deref() == Some(name))
+            .cloned()
+            .collect()
+    }
+}
+
+/// the composer.json of an archive, the one closest to its root
+fn composer_json(content: &[u8]) -> Option<Map<String, Value>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(content)).ok()?;
+    let name = archive
+        .file_names()
+        .filter(|name| {
+            let path = Path::new(name);
+            path.file_name().is_some_and(|f| f == "composer.json") && path.components().count() <= 2
+        })
+        .min_by_key(|name| name.len())?
+        .to_owned();
+
+    let mut json = String::new();
+    archive
+        .by_name(&name)
+        .ok()?
+        .read_to_string(&mut json)
+        .ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+/// mark the dists of `items` which are archives of the artifact repositories
+/// in `dirs` as local, like the packages read by `ArtifactRepository`, as the
+/// flag is not stored in composer.lock
+pub(crate) fn mark_local<'a>(items: impl Iterator<Item = &'a mut Version>, dirs: &[PathBuf]) {
+    let dirs: Vec<PathBuf> = dirs
+        .iter()
+        .filter_map(|dir| canonicalize(dir).ok())
+        .collect();
+    for dist in items.filter_map(|item| item.dist.as_mut()) {
+        let path = Path::new(dist.url.trim_start_matches("file://"));
+        let parent = canonicalize(path)
+            .ok()
+            .and_then(|path| path.parent().map(Path::to_path_buf));
+        dist.local = parent.is_some_and(|parent| dirs.contains(&parent));
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::fs::write;
+    use std::io::Write;
+
+    use zip::write::SimpleFileOptions;
+
+    use super::*;
+
+    /// a package archive, its files in the directory `prefix`
+    pub(crate) fn archive(prefix: &str, json: Value) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default();
+        zip.start_file(format!("{}composer.json", prefix), options)
+            .unwrap();
+        zip.write_all(json.to_string().as_bytes()).unwrap();
+        zip.start_file(format!("{}src/Foo.php", prefix), options)
+            .unwrap();
+        zip.write_all(b"<?php\n").unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_versions() {
+        let root = tempfile::tempdir().unwrap();
+        let foo = archive("", json!({"name": "Acme/Foo", "version": "1.0.0"}));
+        write(root.path().join("foo-1.0.0.zip"), &foo).unwrap();
+        write(
+            root.path().join("foo-1.1.0.zip"),
+            archive("foo/", json!({"name": "acme/foo", "version": "v1.1.0"})),
+        )
+        .unwrap();
+        // without version, or not a zip
+        write(
+            root.path().join("bar.zip"),
+            archive("", json!({"name": "acme/bar"})),
+        )
+        .unwrap();
+        write(root.path().join("baz.zip"), b"not a zip").unwrap();
+        write(root.path().join("README"), b"").unwrap();
+
+        let repo = ArtifactRepository::new(&root.path().to_string_lossy()).unwrap();
+        let versions = repo.versions("acme/foo");
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].version, "1.0.0");
+        assert_eq!(versions[1].version_normalized, "1.1.0.0");
+
+        let dist = versions[0].dist.as_ref().unwrap();
+        assert_eq!(
+            dist.url,
+            root.path().join("foo-1.0.0.zip").to_string_lossy()
+        );
+        {
+            use sha1::{Digest, Sha1};
+            assert_eq!(dist.shasum, Some(hex::encode(Sha1::digest(&foo))));
+        }
+        assert!(dist.local);
+        assert!(repo.versions("acme/bar").is_empty());
+    }
+
+    #[test]
+    fn test_mark_local() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = root.path().join("zips");
+        std::fs::create_dir_all(repo.join("nested")).unwrap();
+        for file in ["zips/foo.zip", "zips/nested/foo.zip", "foo.zip"] {
+            write(root.path().join(file), b"").unwrap();
+        }
+
+        let path = |file: &str| root.path().join(file).to_string_lossy().into_owned();
+        let mut items: Vec<Version> = [
+            path("zips/foo.zip"),
+            format!("file://{}", path("zips/foo.zip")),
+            path("zips/nested/foo.zip"),
+            path("foo.zip"),
+            path("zips/missing.zip"),
+            "https://example.com/zips/foo.zip".to_owned(),
+        ]
+        .into_iter()
+        .map(|url| {
+            serde_json::from_value(json!({
+                "name": "acme/foo",
+                "version": "1.0.0",
+                "dist": {"type": "zip", "url": url},
+            }))
+            .unwrap()
+        })
+        .collect();
+        mark_local(items.iter_mut(), &[repo]);
+
+        let local: Vec<bool> = items
+            .iter()
+            .map(|v| v.dist.as_ref().unwrap().local)
+            .collect();
+        assert_eq!(local, [true, true, false, false, false, false]);
+    }
+}
@@ -0,0 +1,97 @@
+//! a `package` repository, packages declared in composer.json itself
+//!
+//! `package` is one version or a list of versions, written like the versions of
+//! p2 files, for libraries without a composer.json of their own.
+
+use serde_json::{json, Value};
+
+use crate::{constraint::normalize, error::ComposerError, package::Version};
+
+#[derive(Debug)]
+pub(crate) struct PackageRepository {
+    packages: Vec<Version>,
+}
+
+impl PackageRepository {
+    /// read the versions of `package`
+    pub(crate) fn new(package: Option<&Value>) -> Result<Self, ComposerError> {
+        let list = match package {
+            Some(Value::Array(list)) => list.clone(),
+            Some(item @ Value::Object(_)) => vec![item.clone()],
+            _ => {
+                return Err(ComposerError::InvalidJson(
+                    "a package repository needs a package".to_owned(),
+                ))
+            }
+        };
+
+        let mut packages = Vec::new();
+        for mut json in list {
+            let (Some(name), Some(version)) = (
+                json.get("name").and_then(Value::as_str),
+                json.get("version").and_then(Value::as_str),
+            ) else {
+                return Err(ComposerError::InvalidJson(format!(
+                    "a package of a package repository needs a name and a version: {}",
+                    json
+                )));
+            };
+            let name = name.to_lowercase();
+            let normalized = normalize(version)?;
+            json["name"] = json!(name);
+            json["version_normalized"] = json!(normalized);
+
+            packages.push(serde_json::from_value(json)?);
+        }
+
+        Ok(PackageRepository { packages })
+    }
+
+    pub(crate) fn versions(&self, name: &str) -> Vec<Version> {
+        self.packages
+            .iter()
+            .filter(|item| item.name.as_deref() == Some(name))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions() {
+        let repo = PackageRepository::new(Some(&json!([
+            {
+                "name": "Legacy/Lib",
+                "version": "1.0.0",
+                "dist": {"url": "https://example.com/lib-1.0.0.zip", "type": "zip"},
+                "autoload": {"classmap": ["src/"]}
+            },
+            {
+                "name": "legacy/lib",
+                "version": "2.0.0",
+                "source": {"url": "https://example.com/lib.git", "type": "git", "reference": "v2.0.0"}
+            }
+        ])))
+        .unwrap();
+
+        let versions = repo.versions("legacy/lib");
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].version_normalized, "1.0.0.0");
+        let dist = versions[0].dist.as_ref().unwrap();
+        assert_eq!(dist.reference, "");
+        assert!(versions[0].autoload.is_some());
+        assert_eq!(versions[1].source.as_ref().unwrap().reference, "v2.0.0");
+
+        let repo = PackageRepository::new(Some(&json!({"name": "legacy/lib", "version": "1.0"})));
+        assert_eq!(repo.unwrap().versions("legacy/lib").len(), 1);
+
+        assert!(PackageRepository::new(None).is_err());
+        assert!(PackageRepository::new(Some(&json!({"name": "legacy/lib"}))).is_err());
+        assert!(
+            PackageRepository::new(Some(&json!({"name": "legacy/lib", "version": "x"}))).is_err()
+        );
+    }
+}
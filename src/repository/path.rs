@@ -0,0 +1,281 @@
+//! a `path` repository, packages in local directories like `../packages/*`
+//!
+//! The version of a package is the `version` of its composer.json, or
+//! `dev-<branch>` of the git checkout it is in. Packages are installed as a
+//! symlink to their directory, or as a copy with `"options": {"symlink": false}`.
+
+use std::{
+    fs::{copy, create_dir_all, read_dir, read_to_string, remove_dir_all, remove_file},
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
+
+use regex::Regex;
+use serde_json::{json, Map, Value};
+
+use crate::{constraint::normalize, error::ComposerError, package::Version};
+
+#[derive(Debug)]
+pub(crate) struct PathRepository {
+    packages: Vec<Version>,
+}
+
+impl PathRepository {
+    /// read the packages of every directory matching `url`
+    pub(crate) fn new(url: &str, options: &Map<String, Value>) -> Result<Self, ComposerError> {
+        use sha1::{Digest, Sha1};
+
+        let symlink = options
+            .get("options")
+            .and_then(|o| o.get("symlink"))
+            .and_then(Value::as_bool);
+
+        let mut packages = Vec::new();
+        for dir in expand(url) {
+            let file = dir.join("composer.json");
+            if !file.exists() {
+                continue;
+            }
+            let content = read_to_string(&file)?;
+            let mut json: Map<String, Value> = serde_json::from_str(&content)?;
+            if !json.get("name").is_some_and(Value::is_string) {
+                continue;
+            }
+
+            let version = match json.get("version").and_then(Value::as_str) {
+                Some(version) => version.to_owned(),
+                None => format!("dev-{}", branch(&dir).unwrap_or_else(|| "main".to_owned())),
+            };
+
+            json.insert("version_normalized".to_owned(), json!(normalize(&version)?));
+            json.insert("version".to_owned(), json!(version));
+            json.insert(
+                "dist".to_owned(),
+                json!({
+                    "type": "path",
+                    "url": dir.to_string_lossy().trim_start_matches("./"),
+                    "reference": hex::encode(Sha1::digest(content.as_bytes())),
+                }),
+            );
+            if let Some(symlink) = symlink {
+                json.insert(
+                    "transport-options".to_owned(),
+                    json!({ "symlink": symlink }),
+                );
+            }
+
+            let mut item: Version = serde_json::from_value(Value::Object(json))?;
+            item.name = item.name.map(|name| name.to_lowercase());
+            packages.push(item);
+        }
+
+        Ok(PathRepository { packages })
+    }
+
+    pub(crate) fn versions(&self, name: &str) -> Vec<Version> {
+        self.packages
+            .iter()
+            .filter(|item| item.name.as_deref() == Some(name))
+            .cloned()
+            .collect()
+    }
+}
+
+/// the directories matching `url`, `*` matches a part of one path component
+fn expand(url: &str) -> Vec<PathBuf> {
+    let mut dirs = vec![if url.starts_with('/') {
+        PathBuf::from("/")
+    } else {
+        PathBuf::new()
+    }];
+
+    for part in url.split('/').filter(|p| !p.is_empty()) {
+        if !part.contains('*') {
+            dirs.iter_mut().for_each(|dir| dir.push(part));
+            continue;
+        }
+        let pattern = format!("^{}$", regex::escape(part).replace("\\*", ".*"));
+        let Ok(pattern) = Regex::new(&pattern) else {
+            return Vec::new();
+        };
+
+        let mut matched = Vec::new();
+        for dir in dirs.iter() {
+            let read = if dir.as_os_str().is_empty() {
+                read_dir(".")
+            } else {
+                read_dir(dir)
+            };
+            let Ok(entries) = read else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let file_name = entry.file_name();
+                let file_name = file_name.to_string_lossy();
+                if entry.path().is_dir() && pattern.is_match(&file_name) {
+                    matched.push(dir.join(file_name.as_ref()));
+                }
+            }
+        }
+        matched.sort();
+        dirs = matched;
+    }
+
+    dirs.into_iter().filter(|dir| dir.is_dir()).collect()
+}
+
+/// the branch checked out in the git repository of `dir`
+fn branch(dir: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--abbrev-ref", "HEAD"])
+        .current_dir(dir)
+        .output()
+        .ok()?;
+    let branch = String::from_utf8(output.stdout).ok()?.trim().to_owned();
+    if !output.status.success() || branch.is_empty() || branch == "HEAD" {
+        return None;
+    }
+    Some(branch)
+}
+
+/// install the package of a path repository at `target`, a symlink when
+/// possible, always with `symlink: true`, never with `symlink: false`
+pub(crate) fn install(item: &Version, target: &Path) -> Result<(), ComposerError> {
+    let dist = item.dist.as_ref().expect("not found dist field");
+    let source = Path::new(&dist.url);
+    let symlink = item
+        .transport_options
+        .as_ref()
+        .and_then(|o| o.get("symlink"))
+        .and_then(Value::as_bool);
+
+    if let Ok(meta) = target.symlink_metadata() {
+        if meta.is_dir() {
+            remove_dir_all(target)?;
+        } else {
+            remove_file(target)?;
+        }
+    }
+    if let Some(parent) = target.parent() {
+        create_dir_all(parent)?;
+    }
+
+    if symlink != Some(false) {
+        // relative to the directory of the link, like `../../packages/foo`
+        let link = if source.is_absolute() {
+            source.to_path_buf()
+        } else {
+            let depth = target
+                .components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .count()
+                .saturating_sub(1);
+            let mut link: PathBuf = std::iter::repeat_n("..", depth).collect();
+            link.push(source);
+            link
+        };
+        match make_symlink(&link, target) {
+            Ok(()) => return Ok(()),
+            Err(e) if symlink == Some(true) => return Err(e.into()),
+            Err(_) => {}
+        }
+    }
+
+    mirror(source, target)
+}
+
+#[cfg(unix)]
+fn make_symlink(link: &Path, target: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(link, target)
+}
+
+#[cfg(not(unix))]
+fn make_symlink(_link: &Path, _target: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// copy `source` to `target`, without its `.git` and `vendor`
+fn mirror(source: &Path, target: &Path) -> Result<(), ComposerError> {
+    create_dir_all(target)?;
+    for entry in read_dir(source)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let path = entry.path();
+        if path.is_dir() {
+            if name == ".git" || name == "vendor" {
+                continue;
+            }
+            mirror(&path, &target.join(name))?;
+        } else {
+            copy(&path, target.join(name))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_link, File};
+    use std::io::Write;
+
+    use super::*;
+
+    fn package(root: &Path, dir: &str, json: Value) {
+        let dir = root.join(dir);
+        create_dir_all(dir.join("src")).unwrap();
+        let mut f = File::create(dir.join("composer.json")).unwrap();
+        f.write_all(json.to_string().as_bytes()).unwrap();
+        File::create(dir.join("src/Foo.php")).unwrap();
+    }
+
+    #[test]
+    fn test_versions() {
+        let root = tempfile::tempdir().unwrap();
+        package(
+            root.path(),
+            "packages/foo",
+            json!({"name": "Acme/Foo", "version": "1.2.0", "require": {"acme/bar": "*"}}),
+        );
+        package(root.path(), "packages/bar", json!({"name": "acme/bar"}));
+        package(root.path(), "other/baz", json!({"name": "acme/baz"}));
+
+        let url = format!("{}/packages/*", root.path().display());
+        let repo = PathRepository::new(&url, &Map::new()).unwrap();
+
+        let foo = repo.versions("acme/foo");
+        assert_eq!(foo.len(), 1);
+        assert_eq!(foo[0].version, "1.2.0");
+        assert_eq!(foo[0].version_normalized, "1.2.0.0");
+        let dist = foo[0].dist.as_ref().unwrap();
+        assert_eq!(dist._type, "path");
+        assert_eq!(dist.url, format!("{}/packages/foo", root.path().display()));
+        assert!(foo[0].require.is_some());
+
+        // not in a git checkout
+        assert_eq!(repo.versions("acme/bar")[0].version, "dev-main");
+        assert!(repo.versions("acme/baz").is_empty());
+    }
+
+    #[test]
+    fn test_install() {
+        let root = tempfile::tempdir().unwrap();
+        package(
+            root.path(),
+            "packages/foo",
+            json!({"name": "acme/foo", "version": "1.0.0"}),
+        );
+        let source = root.path().join("packages/foo");
+
+        let mut options = Map::new();
+        options.insert("options".to_owned(), json!({"symlink": false}));
+        for (options, link) in [(Map::new(), true), (options, false)] {
+            let repo = PathRepository::new(&source.to_string_lossy(), &options).unwrap();
+            let item = &repo.versions("acme/foo")[0];
+
+            let target = root.path().join("vendor/acme/foo");
+            install(item, &target).unwrap();
+            assert_eq!(read_link(&target).is_ok(), link);
+            assert!(target.join("src/Foo.php").exists());
+        }
+    }
+}
@@ -0,0 +1,407 @@
+//! a `vcs` or `git` repository, read with the git command line
+//!
+//! The repository is mirrored into `vcs/` of the cache. Every tag whose name is
+//! a version and every branch is a version of the package, read from the
+//! composer.json of its commit. A package is installed from a `git archive` of
+//! its commit, or as a checkout with `preferred-install: source`.
+
+use std::{
+    fs::create_dir_all,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    auth::{redact, Auth},
+    constraint::{normalize, normalize_branch},
+    error::ComposerError,
+    package::Version,
+};
+
+#[derive(Debug)]
+pub(crate) struct VcsRepository {
+    packages: Vec<Version>,
+}
+
+impl VcsRepository {
+    /// mirror the repository at `url`, and read the composer.json of its tags and branches
+    pub(crate) fn new(url: &str, cache_dir: &Path, auth: &Auth) -> Result<Self, ComposerError> {
+        let mirror = mirror(url, cache_dir, auth)?;
+
+        let refs = git(
+            &mirror,
+            &[
+                "for-each-ref",
+                "--format=%(refname)%09%(objectname)%09%(*objectname)",
+                "refs/tags",
+                "refs/heads",
+            ],
+        )?;
+
+        let mut packages = Vec::new();
+        for line in String::from_utf8_lossy(&refs).lines() {
+            let mut parts = line.split('\t');
+            let (Some(refname), Some(object), peeled) = (parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+            // annotated tags point to the tag object
+            let commit = peeled.filter(|p| !p.is_empty()).unwrap_or(object);
+
+            let (version, normalized) = if let Some(tag) = refname.strip_prefix("refs/tags/") {
+                match normalize(tag) {
+                    Ok(normalized) => (tag.to_owned(), normalized),
+                    Err(_) => continue,
+                }
+            } else if let Some(branch) = refname.strip_prefix("refs/heads/") {
+                let normalized = normalize_branch(branch);
+                if normalized.starts_with("dev-") {
+                    (normalized.clone(), normalized)
+                } else {
+                    (format!("{}-dev", branch), normalized)
+                }
+            } else {
+                continue;
+            };
+
+            let Ok(content) = git(&mirror, &["show", &format!("{}:composer.json", commit)]) else {
+                continue;
+            };
+            let Ok(mut json) = serde_json::from_slice::<Map<String, Value>>(&content) else {
+                continue;
+            };
+            if !json.get("name").is_some_and(Value::is_string) {
+                continue;
+            }
+
+            json.insert("version".to_owned(), json!(version));
+            json.insert("version_normalized".to_owned(), json!(normalized));
+            json.insert(
+                "source".to_owned(),
+                json!({ "type": "git", "url": url, "reference": commit }),
+            );
+            json.remove("dist");
+
+            let mut item: Version = serde_json::from_value(Value::Object(json))?;
+            item.name = item.name.map(|name| name.to_lowercase());
+            packages.push(item);
+        }
+
+        Ok(VcsRepository { packages })
+    }
+
+    pub(crate) fn versions(&self, name: &str) -> Vec<Version> {
+        self.packages
+            .iter()
+            .filter(|item| item.name.as_deref() == Some(name))
+            .cloned()
+            .collect()
+    }
+}
+
+/// `vcs/<url>` of the cache, the bare mirror of the repository at `url`,
+/// the same for the url of composer.lock, which has no password
+fn mirror_dir(url: &str, cache_dir: &Path) -> PathBuf {
+    cache_dir
+        .join("vcs")
+        .join(redact(url).replace([':', '/', '\\'], "-"))
+}
+
+/// clone `url` into the cache, or fetch it when it is there already
+fn mirror(url: &str, cache_dir: &Path, auth: &Auth) -> Result<PathBuf, ComposerError> {
+    let dir = mirror_dir(url, cache_dir);
+    let headers = auth.headers(url);
+    if dir.join("HEAD").exists() {
+        remote(&dir, &["remote", "update", "--prune"], &headers)?;
+    } else {
+        create_dir_all(&dir)?;
+        // from the current directory, `url` may be a relative path
+        remote(
+            Path::new("."),
+            &[
+                "clone",
+                "--mirror",
+                "--quiet",
+                "--",
+                positional(url)?,
+                &dir.to_string_lossy(),
+            ],
+            &headers,
+        )?;
+    }
+    Ok(dir)
+}
+
+/// a zip of the commit `reference` of the repository at `url`, its files in a
+/// top directory like the archives of other repositories
+pub(crate) fn archive(
+    url: &str,
+    reference: &str,
+    cache_dir: &Path,
+    auth: &Auth,
+) -> Result<Vec<u8>, ComposerError> {
+    let dir = mirror_dir(url, cache_dir);
+    positional(reference)?;
+    if !dir.join("HEAD").exists() || commit(&dir, reference).is_err() {
+        mirror(url, cache_dir, auth)?;
+    }
+
+    let commit = commit(&dir, reference)?;
+    git(
+        &dir,
+        &["archive", "--format=zip", "--prefix=package/", &commit],
+    )
+}
+
+/// clone the repository at `url` into `target`, at the commit `reference`
+pub(crate) fn checkout(
+    url: &str,
+    reference: &str,
+    target: &Path,
+    auth: &Auth,
+) -> Result<(), ComposerError> {
+    if let Some(parent) = target.parent() {
+        create_dir_all(parent)?;
+    }
+    positional(reference)?;
+    remote(
+        Path::new("."),
+        &[
+            "clone",
+            "--quiet",
+            "--no-checkout",
+            "--",
+            positional(url)?,
+            &target.to_string_lossy(),
+        ],
+        &auth.headers(url),
+    )?;
+    let commit = commit(target, reference)?;
+    git(target, &["checkout", "--quiet", &commit, "--"])?;
+    Ok(())
+}
+
+/// the commit `reference` points to in the repository at `dir`
+fn commit(dir: &Path, reference: &str) -> Result<String, ComposerError> {
+    let output = git(
+        dir,
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{}^{{commit}}", positional(reference)?),
+        ],
+    )?;
+    Ok(String::from_utf8_lossy(&output).trim().to_owned())
+}
+
+/// `arg`, unless git would read it as an option: urls and references come
+/// from the metadata of repositories and from composer.lock
+fn positional(arg: &str) -> Result<&str, ComposerError> {
+    if arg.starts_with('-') {
+        return Err(ComposerError::Git(format!(
+            "{} is not a valid url or reference",
+            redact(arg)
+        )));
+    }
+    Ok(arg)
+}
+
+/// run git in `dir`, its output when it succeeds
+fn git(dir: &Path, args: &[&str]) -> Result<Vec<u8>, ComposerError> {
+    remote(dir, args, &[])
+}
+
+/// run git in `dir`, sending `headers` like the credentials of auth.json to
+/// the http(s) remotes; they are passed in the environment, so that they are
+/// neither in the arguments nor in the errors
+fn remote(dir: &Path, args: &[&str], headers: &[String]) -> Result<Vec<u8>, ComposerError> {
+    let mut command = Command::new("git");
+    if !headers.is_empty() {
+        command.env("GIT_CONFIG_COUNT", headers.len().to_string());
+        for (i, header) in headers.iter().enumerate() {
+            command
+                .env(format!("GIT_CONFIG_KEY_{}", i), "http.extraHeader")
+                .env(format!("GIT_CONFIG_VALUE_{}", i), header);
+        }
+    }
+    let output = command.args(args).current_dir(dir).output()?;
+    if !output.status.success() {
+        // the urls may have credentials
+        let args: Vec<String> = args.iter().map(|arg| redact(arg)).collect();
+        return Err(ComposerError::Git(format!(
+            "git {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::fs::write;
+
+    use super::*;
+
+    /// run git in `dir` with a fixed author, so that commits are reproducible
+    fn run(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_AUTHOR_NAME", "phpp")
+            .env("GIT_AUTHOR_EMAIL", "phpp@example.com")
+            .env("GIT_AUTHOR_DATE", "2024-01-01T00:00:00Z")
+            .env("GIT_COMMITTER_NAME", "phpp")
+            .env("GIT_COMMITTER_EMAIL", "phpp@example.com")
+            .env("GIT_COMMITTER_DATE", "2024-01-01T00:00:00Z")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    }
+
+    /// a bare repository in `root` with a commit of `composer.json` for every
+    /// `(composer.json, tag)`, on `main`, and a `2.x` branch from the first commit;
+    /// returns its path and the commit of every tag
+    pub(crate) fn bare_repo(root: &Path, commits: &[(Value, &str)]) -> (PathBuf, Vec<String>) {
+        let work = root.join("work");
+        create_dir_all(work.join("src")).unwrap();
+        run(&work, &["init", "--quiet", "--initial-branch=main"]);
+
+        let mut refs = Vec::new();
+        for (json, tag) in commits {
+            write(work.join("composer.json"), json.to_string()).unwrap();
+            write(work.join("src/Foo.php"), format!("<?php // {}\n", tag)).unwrap();
+            run(&work, &["add", "-A"]);
+            run(&work, &["commit", "--quiet", "-m", tag]);
+            run(&work, &["tag", "-a", tag, "-m", tag]);
+            refs.push(run(&work, &["rev-parse", "HEAD"]));
+        }
+        run(&work, &["branch", "2.x", &refs[0]]);
+
+        let bare = root.join("repo.git");
+        run(
+            root,
+            &[
+                "clone",
+                "--quiet",
+                "--bare",
+                "work",
+                &bare.to_string_lossy(),
+            ],
+        );
+        (bare, refs)
+    }
+
+    #[test]
+    fn test_versions() {
+        let root = tempfile::tempdir().unwrap();
+        let (bare, refs) = bare_repo(
+            root.path(),
+            &[
+                (json!({"name": "Acme/Foo"}), "v1.0.0"),
+                (
+                    json!({"name": "acme/foo", "require": {"php": ">=8.1"}}),
+                    "1.1.0",
+                ),
+                (json!({"name": "acme/foo"}), "not-a-version"),
+            ],
+        );
+        let url = bare.to_string_lossy();
+        let cache_dir = root.path().join("cache");
+
+        let repo = VcsRepository::new(&url, &cache_dir, &Auth::default()).unwrap();
+        let mut versions: Vec<(String, String)> = repo
+            .versions("acme/foo")
+            .into_iter()
+            .map(|item| {
+                let source = item.source.unwrap();
+                assert_eq!(source._type, "git");
+                assert_eq!(source.url, url);
+                assert!(item.dist.is_none());
+                (item.version, source.reference)
+            })
+            .collect();
+        versions.sort();
+        assert_eq!(
+            versions,
+            [
+                ("1.1.0".to_owned(), refs[1].clone()),
+                ("2.x-dev".to_owned(), refs[0].clone()),
+                ("dev-main".to_owned(), refs[2].clone()),
+                ("v1.0.0".to_owned(), refs[0].clone()),
+            ]
+        );
+
+        // fetched again when the mirror is there
+        let repo = VcsRepository::new(&url, &cache_dir, &Auth::default()).unwrap();
+        assert_eq!(repo.versions("acme/foo").len(), 4);
+    }
+
+    #[test]
+    fn test_install() {
+        let root = tempfile::tempdir().unwrap();
+        let (bare, refs) = bare_repo(
+            root.path(),
+            &[
+                (json!({"name": "acme/foo"}), "1.0.0"),
+                (json!({"name": "acme/foo"}), "1.1.0"),
+            ],
+        );
+        let url = bare.to_string_lossy();
+
+        // the mirror is cloned when it is not there yet
+        let content =
+            archive(&url, &refs[0], &root.path().join("cache"), &Auth::default()).unwrap();
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(content)).unwrap();
+        let mut file = String::new();
+        std::io::Read::read_to_string(&mut zip.by_name("package/src/Foo.php").unwrap(), &mut file)
+            .unwrap();
+        assert_eq!(file, "<?php // 1.0.0\n");
+
+        let target = root.path().join("vendor/acme/foo");
+        checkout(&url, &refs[0], &target, &Auth::default()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(target.join("src/Foo.php")).unwrap(),
+            "<?php // 1.0.0\n"
+        );
+        assert!(target.join(".git").exists());
+    }
+
+    #[test]
+    fn test_options() {
+        let root = tempfile::tempdir().unwrap();
+        let (bare, refs) = bare_repo(root.path(), &[(json!({"name": "acme/foo"}), "1.0.0")]);
+        let url = bare.to_string_lossy();
+        let cache_dir = root.path().join("cache");
+        let written = root.path().join("written");
+        let option = format!("--output={}", written.to_string_lossy());
+
+        let upload_pack = format!("--upload-pack=touch {}", written.to_string_lossy());
+        assert!(matches!(
+            VcsRepository::new(&upload_pack, &cache_dir, &Auth::default()),
+            Err(ComposerError::Git(_))
+        ));
+        assert!(matches!(
+            archive(&url, &option, &cache_dir, &Auth::default()),
+            Err(ComposerError::Git(_))
+        ));
+        assert!(matches!(
+            checkout(
+                &upload_pack,
+                &refs[0],
+                &root.path().join("a"),
+                &Auth::default()
+            ),
+            Err(ComposerError::Git(_))
+        ));
+        assert!(matches!(
+            checkout(&url, &option, &root.path().join("b"), &Auth::default()),
+            Err(ComposerError::Git(_))
+        ));
+        assert!(!written.exists());
+    }
+}
@@ -0,0 +1,710 @@
+//! pick one version for every required package
+//!
+//! The resolver works on the versions collected by `P2::down_all`. It walks the
+//! requirements breadth first, tries the candidates of a package from the most
+//! preferred one, and backtracks as soon as a package has no version left which
+//! satisfies every constraint put on it.
+//!
+//! A failure carries its culprits, the packages whose chosen versions lead to it.
+//! Backtracking jumps straight over the choices which are not culprits, as their
+//! other versions would fail the same way, and the versions of the culprits are
+//! remembered as a nogood, so that the same combination fails at once the next
+//! time it is chosen.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap, VecDeque},
+};
+
+use indexmap::IndexMap;
+
+use crate::{
+    constraint::{compare, normalize, Constraint, Stability},
+    package::{is_platform, Context, Require, Version},
+};
+
+/// `name` is required with `constraint` by the package `by`, `None` is composer.json
+#[derive(Debug, Clone)]
+pub(crate) struct Requirement {
+    pub(crate) by: Option<(String, String)>,
+    pub(crate) name: String,
+    pub(crate) constraint: String,
+}
+
+/// why a set of requirements can not be installed
+#[derive(Debug, Clone)]
+pub(crate) enum Conflict {
+    /// no repository provides `name`
+    NotFound { requirement: Requirement },
+    /// no version of `name` satisfies all the `requirements` together
+    NoVersion {
+        name: String,
+        requirements: Vec<Requirement>,
+    },
+    /// the package at the end of `chain` needs another php version
+    PhpVersion {
+        constraint: String,
+        installed: String,
+        chain: Vec<(String, String)>,
+    },
+    /// the package at the end of `chain` needs a missing php extension
+    Extension {
+        extension: String,
+        chain: Vec<(String, String)>,
+    },
+    /// every version of `name` matching the `requirements` failed, for the reasons in
+    /// `causes`, each with the versions it rejected; the reasons of `omitted` more
+    /// versions are not kept
+    AllFailed {
+        name: String,
+        requirements: Vec<Requirement>,
+        causes: Vec<(Vec<String>, Conflict)>,
+        omitted: usize,
+    },
+}
+
+/// keep at most this many different reasons for the failed versions of one package
+const MAX_CAUSES: usize = 4;
+/// keep the reasons of at most this many levels of failed packages below one
+const MAX_DEPTH: usize = 4;
+/// name at most this many versions sharing a reason
+const MAX_VERSIONS: usize = 3;
+
+impl Conflict {
+    /// explain the conflict as a derivation, one step per line
+    pub(crate) fn explain(&self) -> Vec<String> {
+        let mut lines = vec![
+            "Your requirements could not be resolved to an installable set of packages.".to_owned(),
+        ];
+        self.explain_into(&mut lines, 1);
+        lines
+    }
+
+    fn explain_into(&self, lines: &mut Vec<String>, depth: usize) {
+        let indent = format!("{}- ", "  ".repeat(depth));
+        match self {
+            Conflict::NotFound { requirement } => lines.push(format!(
+                "{indent}{}, but {} is not found in any repository",
+                describe(requirement),
+                requirement.name
+            )),
+            Conflict::NoVersion { name, requirements } => {
+                let reasons: Vec<String> = requirements.iter().map(describe).collect();
+                let tail = if requirements.len() > 1 {
+                    format!("so no version of {name} satisfies all of them")
+                } else {
+                    format!("but no version of {name} matches it")
+                };
+                lines.push(format!("{indent}{}, {tail}", reasons.join(", but ")));
+            }
+            Conflict::PhpVersion {
+                constraint,
+                installed,
+                chain,
+            } => lines.push(format!(
+                "{indent}{} requires php({constraint}), but your PHP version is {installed}",
+                chain_str(chain)
+            )),
+            Conflict::Extension { extension, chain } => lines.push(format!(
+                "{indent}{} requires ext-{extension}, it is missing from your system. Install or enable PHP's {extension} extension.",
+                chain_str(chain)
+            )),
+            // the versions were rejected by a later constraint on the same package,
+            // which already tells the whole story
+            Conflict::AllFailed { name, causes, .. }
+                if !causes.is_empty()
+                    && causes.iter().all(
+                        |(_, c)| matches!(c, Conflict::NoVersion { name: n, .. } if n == name),
+                    ) =>
+            {
+                if let Some((_, cause)) = causes.last() {
+                    cause.explain_into(lines, depth);
+                }
+            }
+            Conflict::AllFailed {
+                name,
+                requirements,
+                causes,
+                omitted,
+            } => {
+                let reasons: Vec<String> = requirements.iter().map(describe).collect();
+                lines.push(format!(
+                    "{indent}{}, but no version of {name} can be installed:",
+                    reasons.join(" and ")
+                ));
+                for (versions, cause) in causes.iter() {
+                    let start = lines.len();
+                    cause.explain_into(lines, depth + 1);
+                    // the reason of the first version stands for all of them
+                    if versions.len() > 1 {
+                        let first = format!("{name}({})", versions[0]);
+                        let all = format!("{name}({})", list_versions(versions));
+                        for line in lines[start..].iter_mut() {
+                            *line = line.replace(&first, &all);
+                        }
+                    }
+                }
+                if *omitted > 0 {
+                    lines.push(format!(
+                        "{}- and {omitted} more versions of {name}",
+                        "  ".repeat(depth + 1),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// add the failure of `version` of `name` to `causes`, with the versions failing
+    /// for the same reason, or count it in `omitted` when there are enough reasons
+    fn add_cause(
+        causes: &mut Vec<(Vec<String>, Conflict)>,
+        omitted: &mut usize,
+        name: &str,
+        version: String,
+        mut cause: Conflict,
+    ) {
+        cause.truncate(MAX_DEPTH - 1);
+        let key = cause.key(name, &version);
+        if let Some((versions, _)) = causes
+            .iter_mut()
+            .find(|(versions, c)| c.key(name, &versions[0]) == key)
+        {
+            versions.push(version);
+        } else if causes.len() < MAX_CAUSES {
+            causes.push((vec![version], cause));
+        } else {
+            *omitted += 1;
+        }
+    }
+
+    /// the explanation of the failure of `version` of `name`, without the version
+    fn key(&self, name: &str, version: &str) -> Vec<String> {
+        let mut lines = Vec::new();
+        self.explain_into(&mut lines, 0);
+        let version = format!("{name}({version})");
+        let any = format!("{name}(?)");
+        lines
+            .iter()
+            .map(|line| line.replace(&version, &any))
+            .collect()
+    }
+
+    /// add the packages whose versions the explanation names to `names`
+    fn mentioned(&self, names: &mut BTreeSet<String>) {
+        let by = |requirement: &Requirement| requirement.by.as_ref().map(|(n, _)| n.clone());
+        match self {
+            Conflict::NotFound { requirement } => names.extend(by(requirement)),
+            Conflict::NoVersion { requirements, .. } => {
+                names.extend(requirements.iter().filter_map(by))
+            }
+            Conflict::PhpVersion { chain, .. } | Conflict::Extension { chain, .. } => {
+                names.extend(chain.iter().map(|(n, _)| n.clone()))
+            }
+            Conflict::AllFailed {
+                name,
+                requirements,
+                causes,
+                ..
+            } => {
+                let mut inner = BTreeSet::new();
+                for (_, cause) in causes {
+                    cause.mentioned(&mut inner);
+                }
+                // the failed versions of `name` are listed with their causes
+                inner.remove(name);
+                names.extend(inner);
+                names.extend(requirements.iter().filter_map(by));
+            }
+        }
+    }
+
+    /// drop the reasons of the packages more than `depth` levels below this one
+    fn truncate(&mut self, depth: usize) {
+        if let Conflict::AllFailed {
+            causes, omitted, ..
+        } = self
+        {
+            if depth == 0 {
+                *omitted += causes.drain(..).map(|(v, _)| v.len()).sum::<usize>();
+            } else {
+                for (_, cause) in causes.iter_mut() {
+                    cause.truncate(depth - 1);
+                }
+            }
+        }
+    }
+}
+
+/// `1.0.2, 1.0.1 and 1.0.0`, or `1.0.9, 1.0.8, 1.0.7 and 7 more`
+fn list_versions(versions: &[String]) -> String {
+    match versions {
+        [] => String::new(),
+        [version] => version.clone(),
+        [head @ .., last] if versions.len() <= MAX_VERSIONS => {
+            format!("{} and {}", head.join(", "), last)
+        }
+        _ => format!(
+            "{} and {} more",
+            versions[..MAX_VERSIONS].join(", "),
+            versions.len() - MAX_VERSIONS
+        ),
+    }
+}
+
+/// `foo/bar(1.0.0) requires foo/log(^1.0)`
+fn describe(requirement: &Requirement) -> String {
+    let by = match &requirement.by {
+        Some((name, version)) => format!("{}({})", name, version),
+        None => "composer.json".to_owned(),
+    };
+    format!(
+        "{} requires {}({})",
+        by, requirement.name, requirement.constraint
+    )
+}
+
+/// `foo/bar(1.0.0) -> foo/log(1.2.0)`, the packages which led from composer.json to a package
+fn chain_str(chain: &[(String, String)]) -> String {
+    if chain.is_empty() {
+        return "composer.json".to_owned();
+    }
+    chain
+        .iter()
+        .map(|(name, version)| format!("{}({})", name, version))
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+#[derive(Debug, Clone, Default)]
+struct State {
+    selected: IndexMap<String, Version>,
+    /// every constraint seen so far, by package name
+    constraints: HashMap<String, Vec<Requirement>>,
+    pending: VecDeque<Requirement>,
+}
+
+impl State {
+    /// the packages which led from composer.json to `by`
+    fn chain(&self, by: &Option<(String, String)>) -> Vec<(String, String)> {
+        let mut chain = Vec::new();
+        let mut current = by.clone();
+        while let Some((name, version)) = current {
+            if chain.iter().any(|(n, _)| n == &name) {
+                break;
+            }
+            current = self
+                .constraints
+                .get(&name)
+                .and_then(|list| list.first())
+                .and_then(|req| req.by.clone());
+            chain.push((name, version));
+        }
+        chain.reverse();
+        chain
+    }
+}
+
+/// a conflict, and the packages whose chosen versions lead to it: as long as
+/// they keep these versions, no other choice avoids it
+#[derive(Debug, Clone)]
+struct Failure {
+    conflict: Conflict,
+    culprits: BTreeSet<String>,
+}
+
+impl Failure {
+    fn new(conflict: Conflict, culprits: impl IntoIterator<Item = String>) -> Self {
+        Failure {
+            conflict,
+            culprits: culprits.into_iter().collect(),
+        }
+    }
+}
+
+/// versions which can not be installed together, learned from a failure
+struct Nogood {
+    versions: Vec<(String, (String, bool))>,
+    failure: Failure,
+}
+
+pub(crate) struct Resolver<'a> {
+    ctx: &'a Context,
+    learned: RefCell<Vec<Nogood>>,
+    /// the nogoods of every package name
+    learned_by_name: RefCell<HashMap<String, Vec<usize>>>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(ctx: &'a Context) -> Self {
+        Self {
+            ctx,
+            learned: RefCell::default(),
+            learned_by_name: RefCell::default(),
+        }
+    }
+
+    /// resolve the requirements of composer.json, return the chosen versions
+    pub fn resolve(&self, requirements: Vec<Requirement>) -> Result<Vec<Version>, Conflict> {
+        let state = State {
+            pending: requirements.into(),
+            ..Default::default()
+        };
+
+        let state = self.solve(state).map_err(|failure| failure.conflict)?;
+
+        Ok(state.selected.into_values().map(Version::unalias).collect())
+    }
+
+    fn solve(&self, mut state: State) -> Result<State, Failure> {
+        loop {
+            let req = match state.pending.pop_front() {
+                Some(req) => req,
+                None => return Ok(state),
+            };
+            let by: Vec<String> = req.by.iter().map(|(name, _)| name.clone()).collect();
+
+            if is_platform(&req.name) {
+                self.check_platform(&req.name, &req.constraint, state.chain(&req.by))
+                    .map_err(|c| Failure::new(c, by))?;
+                continue;
+            }
+
+            let name = req.name.clone();
+            state
+                .constraints
+                .entry(name.clone())
+                .or_default()
+                .push(req.clone());
+
+            if let Some(selected) = state.selected.get(&name) {
+                if satisfies(&req.constraint, selected) {
+                    continue;
+                }
+                let conflict = Conflict::NoVersion {
+                    requirements: state.constraints[&name].clone(),
+                    name: name.clone(),
+                };
+                return Err(Failure::new(conflict, by.into_iter().chain([name])));
+            }
+
+            if self.ctx.pool.get(&name).is_none_or(|list| list.is_empty()) {
+                return Err(Failure::new(Conflict::NotFound { requirement: req }, by));
+            }
+
+            // the packages whose requirements on `name` decide its candidates
+            let mut culprits: BTreeSet<String> = state.constraints[&name]
+                .iter()
+                .filter_map(|r| r.by.as_ref().map(|(n, _)| n.clone()))
+                .collect();
+            let candidates = self.candidates(&name, &state.constraints[&name]);
+            if candidates.is_empty() {
+                let conflict = Conflict::NoVersion {
+                    requirements: state.constraints[&name].clone(),
+                    name,
+                };
+                return Err(Failure::new(conflict, culprits));
+            }
+
+            let mut causes = Vec::new();
+            let mut omitted = 0;
+            for candidate in candidates {
+                let by = Some((name.clone(), candidate.version.clone()));
+                let mut deps: Vec<(String, String)> = match &candidate.require {
+                    Some(Require::Map(deps)) => deps.clone().into_iter().collect(),
+                    _ => Vec::new(),
+                };
+                deps.sort();
+
+                // platform requirements are checked right away, so that the
+                // next candidate is tried without walking the tree
+                let platform = deps
+                    .iter()
+                    .filter(|(dep_name, _)| is_platform(dep_name))
+                    .try_for_each(|(dep_name, constraint)| {
+                        self.check_platform(dep_name, constraint, state.chain(&by))
+                    });
+                if let Err(c) = platform {
+                    Conflict::add_cause(
+                        &mut causes,
+                        &mut omitted,
+                        &name,
+                        candidate.version.clone(),
+                        c,
+                    );
+                    continue;
+                }
+
+                let mut next = state.clone();
+                next.selected.insert(name.clone(), candidate.clone());
+                for (dep_name, constraint) in deps.into_iter() {
+                    if !is_platform(&dep_name) {
+                        next.pending.push_back(Requirement {
+                            by: by.clone(),
+                            name: dep_name,
+                            constraint,
+                        });
+                    }
+                }
+
+                let result = match self.recall(&name, &next.selected) {
+                    Some(failure) => Err(failure),
+                    None => self.solve(next).inspect_err(|failure| {
+                        self.learn(failure, &state.selected, &name, &candidate)
+                    }),
+                };
+                match result {
+                    Ok(state) => return Ok(state),
+                    // this version takes no part in the failure, so the other
+                    // versions would fail the same way
+                    Err(failure) if !failure.culprits.contains(&name) => return Err(failure),
+                    Err(failure) => {
+                        culprits.extend(failure.culprits.into_iter().filter(|n| n != &name));
+                        Conflict::add_cause(
+                            &mut causes,
+                            &mut omitted,
+                            &name,
+                            candidate.version.clone(),
+                            failure.conflict,
+                        );
+                    }
+                }
+            }
+
+            let conflict = Conflict::AllFailed {
+                requirements: state.constraints[&name].clone(),
+                name,
+                causes,
+                omitted,
+            };
+            return Err(Failure::new(conflict, culprits));
+        }
+    }
+
+    /// remember that the versions of the culprits of `failure` can not be installed
+    /// together, `name` being at `candidate` and the others as in `selected`
+    fn learn(
+        &self,
+        failure: &Failure,
+        selected: &IndexMap<String, Version>,
+        name: &str,
+        candidate: &Version,
+    ) {
+        if failure.culprits.is_empty() {
+            return;
+        }
+        // the packages named by the explanation too, so that it stays true when
+        // the failure is recalled
+        let mut names = failure.culprits.clone();
+        failure.conflict.mentioned(&mut names);
+        let versions: Option<Vec<_>> = names
+            .iter()
+            .map(|culprit| {
+                let version = if culprit == name {
+                    Some(candidate)
+                } else {
+                    selected.get(culprit)
+                };
+                version.map(|v| (culprit.clone(), identity(v)))
+            })
+            .collect();
+        let Some(versions) = versions else {
+            return;
+        };
+
+        let mut learned = self.learned.borrow_mut();
+        let mut by_name = self.learned_by_name.borrow_mut();
+        for (culprit, _) in versions.iter() {
+            by_name
+                .entry(culprit.clone())
+                .or_default()
+                .push(learned.len());
+        }
+        learned.push(Nogood {
+            versions,
+            failure: failure.clone(),
+        });
+    }
+
+    /// the failure of a nogood of `name` which matches the `selected` versions
+    fn recall(&self, name: &str, selected: &IndexMap<String, Version>) -> Option<Failure> {
+        let learned = self.learned.borrow();
+        let by_name = self.learned_by_name.borrow();
+        by_name.get(name)?.iter().find_map(|&i| {
+            let nogood = &learned[i];
+            nogood
+                .versions
+                .iter()
+                .all(|(n, version)| selected.get(n).is_some_and(|v| &identity(v) == version))
+                .then(|| nogood.failure.clone())
+        })
+    }
+
+    /// the versions of `name` matching all constraints and stability rules, the most
+    /// preferred first
+    fn candidates(&self, name: &str, requirements: &[Requirement]) -> Vec<Version> {
+        let list = match self.ctx.pool.get(name) {
+            Some(list) => list,
+            None => return Vec::new(),
+        };
+
+        let constraints: Vec<Constraint> = match requirements
+            .iter()
+            .map(|r| Constraint::parse(&r.constraint))
+            .collect()
+        {
+            Ok(constraints) => constraints,
+            Err(_) => return Vec::new(),
+        };
+
+        let minimum = self
+            .ctx
+            .stability_flags
+            .get(name)
+            .copied()
+            .unwrap_or(self.ctx.minimum_stability);
+
+        let mut candidates: Vec<Version> = list
+            .iter()
+            .filter(|v| stability(v) >= minimum)
+            .filter(|v| {
+                constraints.iter().all(|c| {
+                    c.matches(&v.version_normalized)
+                        || v.alias_of.as_ref().is_some_and(|(_, n)| c.matches(n))
+                })
+            })
+            .cloned()
+            .collect();
+
+        // an alias satisfies everything the aliased version does, so the
+        // aliased version is never a better choice
+        let aliased: Vec<String> = candidates
+            .iter()
+            .filter_map(|v| v.alias_of.as_ref().map(|(_, n)| n.clone()))
+            .collect();
+        candidates.retain(|v| v.alias_of.is_some() || !aliased.contains(&v.version_normalized));
+
+        candidates.sort_by(|a, b| {
+            let newer = compare(&b.version_normalized, &a.version_normalized);
+            if self.ctx.prefer_stable {
+                stability(b).cmp(&stability(a)).then(newer)
+            } else {
+                newer
+            }
+        });
+
+        candidates
+    }
+
+    fn check_platform(
+        &self,
+        name: &str,
+        constraint: &str,
+        chain: Vec<(String, String)>,
+    ) -> Result<(), Conflict> {
+        if name == "php" {
+            let installed = normalize(&self.ctx.php_version).unwrap_or_default();
+            if !matches(constraint, &installed) {
+                return Err(Conflict::PhpVersion {
+                    constraint: constraint.to_owned(),
+                    installed: self.ctx.php_version.to_owned(),
+                    chain,
+                });
+            }
+        } else if let Some(ext) = name.strip_prefix("ext-") {
+            if !self.ctx.php_extensions.iter().any(|e| e == ext) {
+                return Err(Conflict::Extension {
+                    extension: ext.to_owned(),
+                    chain,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// what tells a version apart from the other versions of its package
+fn identity(version: &Version) -> (String, bool) {
+    (
+        version.version_normalized.clone(),
+        version.alias_of.is_some(),
+    )
+}
+
+/// an alias is as stable as the version it aliases
+fn stability(version: &Version) -> Stability {
+    match &version.alias_of {
+        Some((pretty, _)) => Stability::of(pretty),
+        None => Stability::of(&version.version),
+    }
+}
+
+/// does the version, or the version it aliases, satisfy the constraint
+fn satisfies(constraint: &str, version: &Version) -> bool {
+    matches(constraint, &version.version_normalized)
+        || version
+            .alias_of
+            .as_ref()
+            .is_some_and(|(_, normalized)| matches(constraint, normalized))
+}
+
+/// invalid constraints match nothing
+fn matches(constraint: &str, normalized: &str) -> bool {
+    Constraint::parse(constraint).is_ok_and(|c| c.matches(normalized))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `name` failing for every version in `versions`, each with a cause of its own
+    fn all_failed(name: &str, versions: usize, cause: impl Fn(usize) -> Conflict) -> Conflict {
+        let mut causes = Vec::new();
+        let mut omitted = 0;
+        for i in 0..versions {
+            Conflict::add_cause(
+                &mut causes,
+                &mut omitted,
+                name,
+                format!("1.0.{i}"),
+                cause(i),
+            );
+        }
+        Conflict::AllFailed {
+            name: name.to_owned(),
+            requirements: vec![Requirement {
+                by: None,
+                name: name.to_owned(),
+                constraint: "*".to_owned(),
+            }],
+            causes,
+            omitted,
+        }
+    }
+
+    #[test]
+    fn test_explain_limits() {
+        let missing = |i: usize| Conflict::Extension {
+            extension: format!("ext{i}"),
+            chain: vec![],
+        };
+        // a tree deeper and wider than what is shown
+        let conflict = (0..10).fold(all_failed("foo/0", 10, missing), |inner, level| {
+            all_failed(&format!("foo/{}", level + 1), 10, |_| inner.clone())
+        });
+
+        let lines = conflict.explain();
+        assert_eq!(lines.len(), 1 + MAX_DEPTH + 2);
+        assert_eq!(
+            lines[MAX_DEPTH + 2],
+            format!(
+                "{}- and 10 more versions of foo/6",
+                "  ".repeat(MAX_DEPTH + 2)
+            )
+        );
+
+        let lines = all_failed("foo/bar", 10, missing).explain();
+        assert_eq!(lines.len(), 1 + 1 + MAX_CAUSES + 1);
+        assert_eq!(lines[6], "    - and 6 more versions of foo/bar");
+    }
+}
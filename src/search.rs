@@ -1,9 +1,11 @@
 //! search
 
-use reqwest::header::USER_AGENT;
 use serde::Deserialize;
 
-use crate::{error::ComposerError, package::MY_USER_AGENT};
+use crate::{
+    error::ComposerError,
+    repository::{ComposerRepository, RepositorySet},
+};
 
 pub struct Search {
     keyword: String,
@@ -16,19 +18,18 @@ impl Search {
         }
     }
 
-    pub async fn search(&self) -> Result<(), ComposerError> {
-        let response: SearchResult = reqwest::Client::new()
-            .get(format!(
-                "https://packagist.org/search.json?q={}&per_page=15",
-                self.keyword
-            ))
-            .header(USER_AGENT, MY_USER_AGENT)
-            .send()
-            .await?
-            .json()
-            .await?;
-
-        for item in response.results {
+    /// search in every repository, the first result of a package is shown
+    pub async fn search(&self, repos: &RepositorySet) -> Result<(), ComposerError> {
+        let mut results: Vec<SearchItem> = Vec::new();
+        for repo in repos.iter() {
+            for item in self.search_repo(repo).await? {
+                if !results.iter().any(|r| r.name == item.name) {
+                    results.push(item);
+                }
+            }
+        }
+
+        for item in results {
             println!(
                 "\x1b]8;;{}\x07{:30}\x1b]8;;\x07 {}",
                 item.url, item.name, item.description
@@ -36,12 +37,40 @@ impl Search {
         }
         Ok(())
     }
+
+    /// search with the api of the repository, or in the names of its packages
+    async fn search_repo(
+        &self,
+        repo: &ComposerRepository,
+    ) -> Result<Vec<SearchItem>, ComposerError> {
+        let results = match repo.search_url(&self.keyword) {
+            Some(url) => {
+                let response: SearchResult = repo.get(&url).await?.json().await?;
+                response.results
+            }
+            None => {
+                let keyword = self.keyword.to_lowercase();
+                repo.names()
+                    .into_iter()
+                    .filter(|name| name.contains(&keyword))
+                    .map(|name| SearchItem {
+                        name,
+                        ..Default::default()
+                    })
+                    .collect()
+            }
+        };
+
+        Ok(results)
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 struct SearchItem {
     name: String,
+    #[serde(default)]
     description: String,
+    #[serde(default)]
     url: String,
 }
 